@@ -1,13 +1,11 @@
 #[macro_use]
 extern crate nickel;
 
-use serde_json;
 #[macro_use]
 extern crate serde_derive;
 
 use async_trait::async_trait;
-use nickel::status::StatusCode;
-use nickel::{HttpRouter, MediaType, Nickel, Middleware, MiddlewareResult, Request, Response};
+use nickel::{HttpRouter, Json, MediaType, Nickel, Middleware, MiddlewareResult, Request, Response};
 
 #[derive(Serialize, Deserialize)]
 struct Person {
@@ -58,7 +56,7 @@ async fn main() {
                 first_name: first_name.to_string(),
                 last_name: last_name.to_string(),
             };
-            serde_json::to_value(person).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+            Json::new(person)
         },
     );
 
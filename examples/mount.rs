@@ -12,18 +12,18 @@ async fn main() {
      */
     server.mount("/test/", middleware! { |req|
         format!("Got request with uri = '{}'", req.origin.uri())
-    });
+    }).unwrap();
 
     /*
      * Fall-through behaviour, if StaticFilesHandler does not find a matching file,
      * the request uri must be reset so that it can be matched against other middleware.
      */
-    server.mount("/static/files/", StaticFilesHandler::new("examples/assets/"));
+    server.mount("/static/files/", StaticFilesHandler::new("examples/assets/")).unwrap();
 
     server.mount("/static/files/", middleware! { |req|
         let path = req.path_without_query();
         format!("No static file with path '{}'!", path)
-    });
+    }).unwrap();
 
     server.listen("127.0.0.1:6767").await.unwrap();
 }
@@ -0,0 +1,242 @@
+//! Sending downloads with a `Content-Disposition`, see
+//! `Response::send_attachment`.
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use hyper::{Body, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use tokio::fs::File;
+use tokio::io::AsyncRead;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use crate::middleware::{Halt, MiddlewareResult};
+use crate::mimes::MediaType;
+use crate::response::{mime_from_filename, Response};
+
+/// Whether the client should show a response or save it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Disposition {
+    /// Shown in the browser when it can, like a page.
+    Inline,
+    /// Saved as a file.
+    Attachment,
+}
+
+/// What `Response::send_attachment` sends: the file at a path, bytes in
+/// memory, or whatever an async reader produces.
+pub struct Source {
+    kind: Kind,
+}
+
+enum Kind {
+    Path(PathBuf),
+    Bytes(Bytes),
+    Reader(Pin<Box<dyn AsyncRead + Send>>, Option<u64>),
+}
+
+impl Source {
+    /// The file at `path`.
+    pub fn path<P: Into<PathBuf>>(path: P) -> Source {
+        Source { kind: Kind::Path(path.into()) }
+    }
+
+    /// `bytes` from memory.
+    pub fn bytes<B: Into<Bytes>>(bytes: B) -> Source {
+        Source { kind: Kind::Bytes(bytes.into()) }
+    }
+
+    /// Everything `reader` produces, which is `len` bytes if known, to set
+    /// the `Content-Length`.
+    pub fn reader<R: AsyncRead + Send + 'static>(reader: R, len: Option<u64>) -> Source {
+        Source { kind: Kind::Reader(Box::pin(reader), len) }
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Source {
+        Source::path(path)
+    }
+}
+
+impl From<&Path> for Source {
+    fn from(path: &Path) -> Source {
+        Source::path(path)
+    }
+}
+
+impl From<Bytes> for Source {
+    fn from(bytes: Bytes) -> Source {
+        Source::bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Source {
+        Source::bytes(bytes)
+    }
+}
+
+impl From<&'static [u8]> for Source {
+    fn from(bytes: &'static [u8]) -> Source {
+        Source::bytes(bytes)
+    }
+}
+
+/// The `Content-Disposition` for `filename`, following RFC 6266.
+///
+/// Names with characters beyond ASCII are sent twice: UTF-8 encoded as
+/// `filename*` (RFC 5987), which all current browsers understand, and with
+/// those characters replaced in `filename` for the others.
+pub fn content_disposition(disposition: Disposition, filename: &str) -> HeaderValue {
+    let mut value = match disposition {
+        Disposition::Inline => "inline".to_string(),
+        Disposition::Attachment => "attachment".to_string()
+    };
+    if filename.is_empty() {
+        return HeaderValue::from_str(&value).unwrap();
+    }
+
+    value.push_str("; filename=\"");
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' => { value.push('\\'); value.push(c) },
+            ' '..='~' => value.push(c),
+            _ => value.push('_')
+        }
+    }
+    value.push('"');
+
+    if !filename.chars().all(|c| matches!(c, ' '..='~')) {
+        value.push_str("; filename*=UTF-8''");
+        for &b in filename.as_bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                value.push(b as char);
+            } else {
+                write!(value, "%{:02X}", b).unwrap();
+            }
+        }
+    }
+    // every byte is visible ASCII or a space by now
+    HeaderValue::from_str(&value).unwrap()
+}
+
+impl<D: Send + 'static + Sync> Response<D> {
+    /// Sends `source` as a download, saved as `filename`.
+    ///
+    /// The media type is taken from the extension of `filename`, unless one
+    /// is set already, and the `Content-Length` is set when the length is
+    /// known. A path which can't be opened gets `404 Not Found`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Request, Response, MiddlewareResult};
+    /// use nickel::attachment::Source;
+    ///
+    /// # #[allow(dead_code)]
+    /// async fn report<D: Send + Sync + 'static>(_: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+    ///     let csv = "day,visits\nmonday,12\n".to_string().into_bytes();
+    ///     res.send_attachment("Übersicht.csv", csv).await
+    /// }
+    ///
+    /// # #[allow(dead_code)]
+    /// async fn backup<D: Send + Sync + 'static>(_: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+    ///     let file = tokio::fs::File::open("/var/backups/db.tar.gz").await.unwrap();
+    ///     res.send_attachment("db.tar.gz", Source::reader(file, None)).await
+    /// }
+    /// ```
+    pub async fn send_attachment<S: Into<Source>>(self, filename: &str, source: S) -> MiddlewareResult<D> {
+        self.send_disposed(Disposition::Attachment, filename, source.into()).await
+    }
+
+    /// Like `send_attachment`, but asks the client to show `source` when it
+    /// can, with `filename` as the name to save it as.
+    pub async fn send_inline<S: Into<Source>>(self, filename: &str, source: S) -> MiddlewareResult<D> {
+        self.send_disposed(Disposition::Inline, filename, source.into()).await
+    }
+
+    async fn send_disposed(mut self, disposition: Disposition, filename: &str, source: Source) -> MiddlewareResult<D> {
+        let (body, len) = match source.kind {
+            Kind::Path(path) => {
+                let opened = match File::open(&path).await {
+                    Ok(file) => file.metadata().await.map(|metadata| (file, metadata)),
+                    Err(e) => Err(e)
+                };
+                match opened {
+                    Ok((file, metadata)) if metadata.is_file() => {
+                        (Body::wrap_stream(FramedRead::new(file, BytesCodec::new())), Some(metadata.len()))
+                    },
+                    Ok(_) => return self.error(StatusCode::NOT_FOUND, format!("Failed to send file '{:?}': Not a file", path)),
+                    Err(e) => return self.error(StatusCode::NOT_FOUND, format!("Failed to send file '{:?}': {}", path, e))
+                }
+            },
+            Kind::Bytes(bytes) => {
+                let len = bytes.len() as u64;
+                (Body::from(bytes), Some(len))
+            },
+            Kind::Reader(reader, len) => (Body::wrap_stream(FramedRead::new(reader, BytesCodec::new())), len)
+        };
+
+        let mime = mime_from_filename(filename).unwrap_or(MediaType::Bin);
+        self.set_header_fallback(&header::CONTENT_TYPE, &mime.into());
+        self.set_header(header::CONTENT_DISPOSITION, content_disposition(disposition, filename));
+        match len {
+            Some(len) => { self.set_header(header::CONTENT_LENGTH, HeaderValue::from(len)); },
+            None => { self.origin.headers_mut().remove(header::CONTENT_LENGTH); }
+        }
+        self.set(StatusCode::OK);
+        self.start();
+        self.set_body(body);
+        Ok(Halt(self))
+    }
+}
+
+#[test]
+fn encodes_filenames() {
+    let disposition = |d, name| content_disposition(d, name).to_str().unwrap().to_string();
+    assert_eq!(disposition(Disposition::Attachment, "report.pdf"), "attachment; filename=\"report.pdf\"");
+    assert_eq!(disposition(Disposition::Inline, "a \"b\"\\c.txt"), "inline; filename=\"a \\\"b\\\"\\\\c.txt\"");
+    assert_eq!(disposition(Disposition::Attachment, "Übersicht 2024.csv"),
+               "attachment; filename=\"_bersicht 2024.csv\"; filename*=UTF-8''%C3%9Cbersicht%202024.csv");
+    assert_eq!(disposition(Disposition::Attachment, "€\n"), "attachment; filename=\"__\"; filename*=UTF-8''%E2%82%AC%0A");
+    assert_eq!(disposition(Disposition::Inline, ""), "inline");
+}
+
+#[tokio::test]
+async fn sends_attachments() {
+    use std::sync::Arc;
+    use hyper::body;
+    use crate::test_util::response;
+
+    async fn send(res: MiddlewareResult<()>) -> (StatusCode, header::HeaderMap, String) {
+        let res = match res {
+            Ok(Halt(res)) => res,
+            Err(err) => err.stream.unwrap(),
+            _ => panic!("expected the response to halt")
+        };
+        let (parts, body) = res.origin.into_parts();
+        (parts.status, parts.headers, String::from_utf8(body::to_bytes(body).await.unwrap().to_vec()).unwrap())
+    }
+
+    let (status, headers, body) = send(response(Arc::new(())).send_attachment("data.json", &b"[1, 2]"[..]).await).await;
+    assert_eq!((status, &body[..]), (StatusCode::OK, "[1, 2]"));
+    assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+    assert_eq!(headers[header::CONTENT_LENGTH], "6");
+    assert_eq!(headers[header::CONTENT_DISPOSITION], "attachment; filename=\"data.json\"");
+
+    let reader = Source::reader(&b"streamed"[..], None);
+    let (status, headers, body) = send(response(Arc::new(())).send_inline("notes", reader).await).await;
+    assert_eq!((status, &body[..]), (StatusCode::OK, "streamed"));
+    assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+    assert!(headers.get(header::CONTENT_LENGTH).is_none());
+    assert_eq!(headers[header::CONTENT_DISPOSITION], "inline; filename=\"notes\"");
+
+    let path = std::env::temp_dir().join(format!("nickel-attachment-{}.txt", std::process::id()));
+    std::fs::write(&path, "on disk").unwrap();
+    let (status, headers, body) = send(response(Arc::new(())).send_attachment("copy.txt", path.clone()).await).await;
+    assert_eq!((status, &body[..]), (StatusCode::OK, "on disk"));
+    assert_eq!(headers[header::CONTENT_LENGTH], "7");
+    std::fs::remove_file(&path).unwrap();
+
+    let (status, _, _) = send(response(Arc::new(())).send_attachment("gone.txt", path).await).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
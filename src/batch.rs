@@ -0,0 +1,279 @@
+//! Running several requests sent as one JSON batch.
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use hyper::{Body, Method, Request as HyperRequest, Response as HyperResponse, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde_json::{self, json, Map, Value};
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::response::Response;
+
+// Headers about the connection or the framing of the batch request, which
+// don't apply to the requests in it.
+const CONNECTION_HEADERS: [HeaderName; 6] = [
+    header::CONNECTION, header::CONTENT_LENGTH, header::TRANSFER_ENCODING,
+    header::TE, header::TRAILER, header::UPGRADE,
+];
+
+/// Accepts a JSON array of requests POSTed to a path, runs each through the
+/// middleware stack and responds with a JSON array of their results.
+///
+/// Each entry looks like
+/// `{"method": "GET", "path": "/users/1", "headers": {...}, "body": "..."}`,
+/// where only `path` is required. A `body` which isn't a string is sent as
+/// JSON. Requests start out with the headers of the batch request, except
+/// for its body headers, with the entry's headers on top. Hop-by-hop and
+/// framing headers like `Connection` and `Content-Length` are left out.
+///
+/// Each result looks like `{"status": 200, "headers": {...}, "body": "..."}`,
+/// where headers with several values, like `Set-Cookie`, are arrays. A body
+/// which isn't UTF-8 is base64 encoded, and the result gets
+/// `"encoding": "base64"`.
+/// An entry which is invalid or can't be dispatched gets
+/// `{"status": 400, "error": "..."}` instead, the other entries are still
+/// run. Batches of more than 50 requests, or the limit set with
+/// `max_requests`, get `413 Payload Too Large` and none are run.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, Batch};
+///
+/// let mut server = Nickel::new();
+/// server.utilize(Batch::new("/batch").concurrency(4).max_requests(20));
+/// ```
+pub struct Batch {
+    path: String,
+    concurrency: usize,
+    max_requests: usize,
+}
+
+impl Batch {
+    /// Serves batches at `path`, running their requests one after another.
+    pub fn new<S: Into<String>>(path: S) -> Batch {
+        Batch {
+            path: path.into(),
+            concurrency: 1,
+            max_requests: 50
+        }
+    }
+
+    /// Runs up to `limit` requests of a batch at the same time. Results are
+    /// still in the order of the batch.
+    ///
+    /// # Panics
+    /// Panics if `limit` is 0.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "Batch concurrency must be at least 1");
+        self.concurrency = limit;
+        self
+    }
+
+    /// Sets how many requests a batch may contain.
+    pub fn max_requests(mut self, limit: usize) -> Self {
+        self.max_requests = limit;
+        self
+    }
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Batch {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        if req.origin.method() != Method::POST || req.path_without_query() != self.path {
+            return res.next_middleware();
+        }
+
+        let entries = match req.raw_body().await {
+            Ok(body) => serde_json::from_slice::<Value>(body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            Err(e) => Err(e)
+        };
+        let entries = match entries {
+            Ok(Value::Array(entries)) => entries,
+            Ok(_) => return res.error(StatusCode::BAD_REQUEST, "A batch must be a JSON array"),
+            Err((status, message)) => return res.error(status, message)
+        };
+        if entries.len() > self.max_requests {
+            return res.error(StatusCode::PAYLOAD_TOO_LARGE,
+                             format!("A batch may contain up to {} requests, not {}", self.max_requests, entries.len()));
+        }
+
+        let req = &*req;
+        let res_ref = &res;
+        let results: Vec<Value> = stream::iter(entries)
+            .map(|entry| async move {
+                let sub = entry_request(req.origin.headers(), entry)
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                res_ref.sub_request(req, sub).await
+            })
+            .buffered(self.concurrency)
+            .map(|result| match result {
+                Ok(sub) => result_json(sub),
+                Err((status, message)) => json!({ "status": status.as_u16(), "error": message })
+            })
+            .collect()
+            .await;
+
+        res.set(StatusCode::OK).set(MediaType::Json);
+        res.send(Value::Array(results).to_string())
+    }
+}
+
+fn entry_request(batch_headers: &HeaderMap, entry: Value) -> Result<HyperRequest<Body>, String> {
+    let mut entry = match entry {
+        Value::Object(entry) => entry,
+        _ => return Err("A batch entry must be a JSON object".to_string())
+    };
+
+    let path = match entry.remove("path") {
+        Some(Value::String(ref path)) if path.starts_with('/') => path.clone(),
+        _ => return Err("A batch entry needs a `path` starting with '/'".to_string())
+    };
+    let method = match entry.remove("method") {
+        Some(Value::String(method)) => method.to_uppercase().parse::<Method>().map_err(|e| e.to_string())?,
+        None => Method::GET,
+        Some(_) => return Err("The `method` of a batch entry must be a string".to_string())
+    };
+    let body = match entry.remove("body") {
+        None | Some(Value::Null) => Body::empty(),
+        Some(Value::String(body)) => Body::from(body),
+        Some(body) => Body::from(body.to_string())
+    };
+
+    let mut sub = HyperRequest::builder().method(method).uri(&path[..]).body(body).map_err(|e| e.to_string())?;
+    for (name, value) in batch_headers {
+        if name != header::CONTENT_TYPE && !CONNECTION_HEADERS.contains(name) {
+            sub.headers_mut().append(name, value.clone());
+        }
+    }
+
+    match entry.remove("headers") {
+        Some(Value::Object(headers)) => {
+            for (name, value) in headers {
+                let value = value.as_str().ok_or_else(|| format!("The value of header `{}` must be a string", name))?;
+                let name = name.parse::<HeaderName>().map_err(|e| e.to_string())?;
+                let value = HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+                if !CONNECTION_HEADERS.contains(&name) {
+                    sub.headers_mut().insert(name, value);
+                }
+            }
+        },
+        None => {},
+        Some(_) => return Err("The `headers` of a batch entry must be an object".to_string())
+    }
+
+    Ok(sub)
+}
+
+fn result_json(sub: HyperResponse<Bytes>) -> Value {
+    let mut headers = Map::new();
+    for name in sub.headers().keys() {
+        // values can't be joined, that would break `Set-Cookie`
+        let mut values: Vec<Value> = sub.headers().get_all(name).iter()
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into())
+            .collect();
+        let value = match values.len() {
+            1 => values.remove(0),
+            _ => Value::Array(values)
+        };
+        headers.insert(name.to_string(), value);
+    }
+
+    let mut result = json!({
+        "status": sub.status().as_u16(),
+        "headers": headers,
+    });
+    match std::str::from_utf8(sub.body()) {
+        Ok(body) => result["body"] = body.into(),
+        Err(_) => {
+            result["body"] = base64(sub.body()).into();
+            result["encoding"] = "base64".into();
+        }
+    }
+    result
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[tokio::test]
+async fn runs_batches() {
+    use std::sync::Arc;
+    use crate::{HttpRouter, Router};
+    use crate::default_error_handler::DefaultErrorHandler;
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_shared};
+
+    let mut router = Router::new();
+    router.get("/users/:id", middleware! { |request|
+        format!("user {} {}", request.param("id").unwrap(),
+                request.origin.headers().get("x-token").map_or("", |t| t.to_str().unwrap()))
+    });
+    router.post("/echo", middleware! { |request|
+        let headers = request.origin.headers();
+        format!("echo {:?} {:?}", headers.get(header::CONTENT_LENGTH), headers.get(header::TRANSFER_ENCODING))
+    });
+    router.get("/cookies", middleware! { |_, mut res|
+        res.headers_mut().append(header::SET_COOKIE, HeaderValue::from_static("a=1"));
+        res.headers_mut().append(header::SET_COOKIE, HeaderValue::from_static("b=2, c"));
+        vec![0xff, 0, b'a', b'b']
+    });
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(DefaultErrorHandler);
+    stack.add_middleware(Batch::new("/batch").concurrency(2).max_requests(7));
+    stack.add_middleware(router);
+    let stack = Arc::new(stack);
+
+    let batch = json!([
+        { "path": "/users/1" },
+        { "path": "/users/2", "headers": { "x-token": "item" } },
+        { "method": "post", "path": "/echo", "body": { "a": 1 },
+          "headers": { "content-length": "100", "transfer-encoding": "chunked" } },
+        { "path": "/nope" },
+        { "path": "relative" },
+        42,
+        { "path": "/cookies" }
+    ]);
+    let mut req = request(Method::POST, "/batch", Arc::new(()));
+    *req.origin.body_mut() = Body::from(batch.to_string());
+    req.origin.headers_mut().insert("x-token", HeaderValue::from_static("batch"));
+    req.origin.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+
+    let (status, body) = run_shared(&stack, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let results: Vec<Value> = serde_json::from_str(&body).unwrap();
+    let summary: Vec<_> = results.iter().map(|r| (r["status"].as_u64().unwrap(), r["body"].as_str())).collect();
+    assert_eq!(summary, vec![(200, Some("user 1 batch")),
+                             (200, Some("user 2 item")),
+                             (200, Some("echo None None")),
+                             (404, Some("")),
+                             (400, None),
+                             (400, None),
+                             (200, Some("/wBhYg=="))]);
+    assert!(results[4]["error"].as_str().unwrap().contains("path"));
+    assert_eq!(results[6]["encoding"], "base64");
+    assert_eq!(results[6]["headers"]["set-cookie"], json!(["a=1", "b=2, c"]));
+    assert_eq!(results[0]["headers"]["content-type"], "text/html");
+    assert!(results[0].get("encoding").is_none());
+
+    let mut req = request(Method::POST, "/batch", Arc::new(()));
+    *req.origin.body_mut() = Body::from("{}");
+    assert_eq!(run_shared(&stack, req).await.0, StatusCode::BAD_REQUEST);
+
+    let mut req = request(Method::POST, "/batch", Arc::new(()));
+    *req.origin.body_mut() = Body::from(json!(vec![json!({ "path": "/users/1" }); 8]).to_string());
+    assert_eq!(run_shared(&stack, req).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+}
@@ -1,5 +1,5 @@
 pub mod response;
 pub mod request;
 
-pub use self::response::Redirect;
+pub use self::response::{Redirect, Redirection};
 pub use self::request::Referer;
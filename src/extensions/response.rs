@@ -1,4 +1,4 @@
-use crate::{Response, MiddlewareResult};
+use crate::{Response, MiddlewareResult, Responder};
 use hyper::header;
 use crate::status::StatusCode;
 
@@ -37,8 +37,6 @@ pub trait Redirect: Sized {
     where T: Into<String>;
 }
 
-// TODO: rework this to return a Responder so it will work with the
-// middleware macro
 impl<D: Send + 'static + Sync> Redirect for Response<D> {
     type Result = MiddlewareResult<D>;
 
@@ -60,3 +58,49 @@ impl<D: Send + 'static + Sync> Redirect for Response<D> {
         }
     }
 }
+
+/// A redirect to return from a handler, like a `middleware!` block, which
+/// doesn't hand the response to `Redirect` itself.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter};
+/// use nickel::extensions::Redirection;
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.get("/old/:page", middleware! { |req|
+///         Redirection::permanently_to(format!("/new/{}", req.param("page").unwrap()))
+///     });
+///     server.get("/home", middleware!(Redirection::to("/")));
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirection {
+    target: String,
+    status: StatusCode,
+}
+
+impl Redirection {
+    /// Redirects to `target` with `302 Found`.
+    pub fn to<T: Into<String>>(target: T) -> Redirection {
+        Redirection::with(target, StatusCode::FOUND)
+    }
+
+    /// Redirects to `target` with `301 Moved Permanently`.
+    pub fn permanently_to<T: Into<String>>(target: T) -> Redirection {
+        Redirection::with(target, StatusCode::MOVED_PERMANENTLY)
+    }
+
+    /// Redirects to `target` with `status`, which has to be a 3xx status.
+    pub fn with<T: Into<String>>(target: T, status: StatusCode) -> Redirection {
+        Redirection { target: target.into(), status }
+    }
+}
+
+impl<D: Send + 'static + Sync> Responder<D> for Redirection {
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        res.redirect_with(self.target, self.status)
+    }
+}
@@ -0,0 +1,330 @@
+//! Broadcasting messages to the subscribers of topics, for fanning out to
+//! streaming clients like Server-Sent Events and WebSockets.
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use futures::stream::Stream;
+
+/// What a `Hub` does when a subscriber's buffer is full because it doesn't
+/// keep up with the messages published.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lagging {
+    /// Drops the oldest buffered message to make room for the new one.
+    DropOldest,
+    /// Drops the new message.
+    DropNewest,
+    /// Unsubscribes the subscriber, its stream ends after the buffered
+    /// messages.
+    Disconnect,
+}
+
+/// Publishes messages to the subscribers of topics. A hub is cheap to clone,
+/// the clones share subscribers, so it can be kept in the server data and
+/// used from any handler.
+///
+/// Each subscriber buffers up to a number of messages, what happens beyond
+/// that is up to the `Lagging` policy. Subscribers are unsubscribed when
+/// their `Subscription` is dropped, e.g. when `Sse` notices that the client
+/// has gone away.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use futures::StreamExt;
+/// use nickel::{Nickel, HttpRouter};
+/// use nickel::hub::{Hub, Lagging};
+/// use nickel::sse::{Event, Sse};
+///
+/// fn main() {
+///     let hub = Hub::<String>::new().capacity(16).lagging(Lagging::DropOldest);
+///     let mut server = Nickel::with_data(hub);
+///
+///     server.get("/rooms/:room/events", middleware! { |req, res| <Hub<String>>
+///         let messages = res.server_data().subscribe(&[req.param("room").unwrap()]);
+///         Sse::new(messages.map(Event::data))
+///     });
+///     server.post("/rooms/:room", middleware! { |req, res| <Hub<String>>
+///         let room = req.param("room").unwrap().to_string();
+///         let received = res.server_data().publish(&room, format!("ping in {}", room));
+///         format!("sent to {} subscribers", received)
+///     });
+/// }
+/// ```
+pub struct Hub<T> {
+    shared: Arc<Shared<T>>,
+    capacity: usize,
+    lagging: Lagging,
+}
+
+struct Shared<T> {
+    topics: Mutex<HashMap<String, Vec<Arc<Subscriber<T>>>>>,
+}
+
+// Ends the subscriptions once no hub is left to publish to them.
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let topics = self.topics.get_mut().unwrap_or_else(|e| e.into_inner());
+        for subscriber in topics.values().flatten() {
+            let mut buffer = subscriber.state.lock().unwrap();
+            buffer.closed = true;
+            if let Some(waker) = buffer.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+struct Subscriber<T> {
+    state: Mutex<Buffer<T>>,
+    capacity: usize,
+    lagging: Lagging,
+}
+
+struct Buffer<T> {
+    messages: VecDeque<T>,
+    missed: u64,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T: Clone + Send + 'static> Hub<T> {
+    /// A hub buffering up to 64 messages per subscriber, dropping the oldest
+    /// ones beyond that.
+    pub fn new() -> Hub<T> {
+        Hub {
+            shared: Arc::new(Shared { topics: Mutex::new(HashMap::new()) }),
+            capacity: 64,
+            lagging: Lagging::DropOldest
+        }
+    }
+
+    /// Sets how many messages each new subscriber buffers.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "A hub needs room for at least one message per subscriber");
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets what happens to new subscribers which fall behind.
+    pub fn lagging(mut self, lagging: Lagging) -> Self {
+        self.lagging = lagging;
+        self
+    }
+
+    /// Subscribes to the messages published to any of `topics`.
+    pub fn subscribe<S: AsRef<str>>(&self, topics: &[S]) -> Subscription<T> {
+        let subscriber = Arc::new(Subscriber {
+            state: Mutex::new(Buffer {
+                messages: VecDeque::new(),
+                missed: 0,
+                closed: false,
+                waker: None
+            }),
+            capacity: self.capacity,
+            lagging: self.lagging
+        });
+
+        let mut names: Vec<String> = topics.iter().map(|t| t.as_ref().to_string()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut subscribed = self.shared.topics.lock().unwrap();
+        for topic in &names {
+            subscribed.entry(topic.clone()).or_default().push(subscriber.clone());
+        }
+
+        Subscription {
+            hub: Arc::downgrade(&self.shared),
+            topics: names,
+            subscriber
+        }
+    }
+
+    /// Sends `message` to the subscribers of `topic`, returning how many of
+    /// them it was buffered for.
+    pub fn publish(&self, topic: &str, message: T) -> usize {
+        let mut topics = self.shared.topics.lock().unwrap();
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return 0
+        };
+
+        let mut received = 0;
+        subscribers.retain(|subscriber| {
+            let mut buffer = subscriber.state.lock().unwrap();
+            if buffer.closed {
+                return false;
+            }
+
+            if buffer.messages.len() < subscriber.capacity {
+                buffer.messages.push_back(message.clone());
+                received += 1;
+            } else {
+                buffer.missed += 1;
+                match subscriber.lagging {
+                    Lagging::DropOldest => {
+                        buffer.messages.pop_front();
+                        buffer.messages.push_back(message.clone());
+                        received += 1;
+                    },
+                    Lagging::DropNewest => {},
+                    Lagging::Disconnect => buffer.closed = true
+                }
+            }
+
+            if let Some(waker) = buffer.waker.take() {
+                waker.wake();
+            }
+            !buffer.closed
+        });
+
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        received
+    }
+
+    /// How many subscribers `topic` has.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        let topics = self.shared.topics.lock().unwrap();
+        topics.get(topic).map_or(0, |subscribers| {
+            subscribers.iter().filter(|s| !s.state.lock().unwrap().closed).count()
+        })
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for Hub<T> {
+    fn default() -> Self {
+        Hub::new()
+    }
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Self {
+        Hub {
+            shared: self.shared.clone(),
+            capacity: self.capacity,
+            lagging: self.lagging
+        }
+    }
+}
+
+/// The messages published to the topics subscribed to with `Hub::subscribe`,
+/// as a `Stream`. Dropping it unsubscribes.
+pub struct Subscription<T> {
+    hub: Weak<Shared<T>>,
+    topics: Vec<String>,
+    subscriber: Arc<Subscriber<T>>,
+}
+
+impl<T> Subscription<T> {
+    /// How many messages were dropped, or not delivered, because the
+    /// subscription fell behind.
+    pub fn missed(&self) -> u64 {
+        self.subscriber.state.lock().unwrap().missed
+    }
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut buffer = self.subscriber.state.lock().unwrap();
+        match buffer.messages.pop_front() {
+            Some(message) => Poll::Ready(Some(message)),
+            None if buffer.closed => Poll::Ready(None),
+            None => {
+                buffer.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let hub = match self.hub.upgrade() {
+            Some(hub) => hub,
+            None => return
+        };
+        let mut topics = hub.topics.lock().unwrap();
+        for topic in &self.topics {
+            if let Some(subscribers) = topics.get_mut(topic) {
+                subscribers.retain(|s| !Arc::ptr_eq(s, &self.subscriber));
+                if subscribers.is_empty() {
+                    topics.remove(topic);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn broadcasts_to_subscribers() {
+    use futures::StreamExt;
+
+    let hub = Hub::new().capacity(2);
+    let mut both = hub.subscribe(&["a", "b", "a"]);
+    let mut a = hub.clone().subscribe(&["a"]);
+
+    assert_eq!(hub.publish("a", 1), 2);
+    assert_eq!(hub.publish("b", 2), 1);
+    assert_eq!(hub.publish("c", 3), 0);
+    assert_eq!(both.next().await, Some(1));
+    assert_eq!(both.next().await, Some(2));
+    assert_eq!(a.next().await, Some(1));
+
+    // `a` has room for two, the oldest is dropped
+    for i in 10..13 {
+        hub.publish("a", i);
+    }
+    assert_eq!(a.missed(), 1);
+    assert_eq!(a.next().await, Some(11));
+    assert_eq!(a.next().await, Some(12));
+
+    drop(both);
+    assert_eq!(hub.subscribers("a"), 1);
+    assert_eq!(hub.subscribers("b"), 0);
+    drop(a);
+    assert_eq!(hub.publish("a", 0), 0);
+}
+
+#[tokio::test]
+async fn applies_the_lag_policy() {
+    use futures::StreamExt;
+
+    let newest = Hub::new().capacity(1).lagging(Lagging::DropNewest);
+    let mut sub = newest.subscribe(&["t"]);
+    assert_eq!(newest.publish("t", 1), 1);
+    assert_eq!(newest.publish("t", 2), 0);
+    assert_eq!(sub.next().await, Some(1));
+    assert_eq!(sub.missed(), 1);
+
+    let strict = Hub::new().capacity(1).lagging(Lagging::Disconnect);
+    let mut sub = strict.subscribe(&["t", "u"]);
+    strict.publish("t", 1);
+    strict.publish("t", 2);
+    assert_eq!(strict.subscribers("t"), 0);
+    assert_eq!(strict.subscribers("u"), 0);
+    assert_eq!(strict.publish("u", 3), 0);
+    assert_eq!(sub.next().await, Some(1));
+    assert_eq!(sub.next().await, None);
+
+    // a waiting subscriber is woken up by a publish
+    let hub = Hub::new();
+    let mut sub = hub.subscribe(&["t"]);
+    let publisher = hub.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        publisher.publish("t", "late");
+    });
+    assert_eq!(sub.next().await, Some("late"));
+
+    // and the subscriptions end with the hub
+    drop(hub);
+    assert_eq!(sub.next().await, None);
+}
@@ -0,0 +1,148 @@
+//! Responding with any serializable value as JSON.
+use hyper::StatusCode;
+use hyper::header::{self, HeaderValue};
+use serde::Serialize;
+use crate::middleware::{Halt, MiddlewareResult};
+use crate::responder::Responder;
+use crate::response::Response;
+
+/// Responds with `value` serialized as JSON, served as
+/// `application/json; charset=utf-8` unless a content type is set already.
+///
+/// A value which can't be serialized, like a map with non-string keys, gets
+/// `500 Internal Server Error`.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// #[macro_use] extern crate serde_derive;
+/// use nickel::{Nickel, HttpRouter, Json, QueryString};
+///
+/// #[derive(Serialize)]
+/// struct Person {
+///     first_name: String,
+///     last_name: String,
+/// }
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.get("/people/:first/:last", middleware! { |req|
+///         let person = Person {
+///             first_name: req.param("first").unwrap().to_string(),
+///             last_name: req.param("last").unwrap().to_string(),
+///         };
+///         // `?callback=show` wraps the JSON for JSONP
+///         let callback = req.query().get("callback").map(|c| c.to_string());
+///         Json::new(person).pretty().jsonp(callback)
+///     });
+/// }
+/// ```
+pub struct Json<T> {
+    value: T,
+    pretty: bool,
+    callback: Option<String>,
+}
+
+impl<T: Serialize> Json<T> {
+    /// Responds with `value`, serialized compactly.
+    pub fn new(value: T) -> Json<T> {
+        Json { value, pretty: false, callback: None }
+    }
+
+    /// Indents the JSON for people to read.
+    pub fn pretty(mut self) -> Self {
+        self.pretty = true;
+        self
+    }
+
+    /// Wraps the JSON in a call to `callback` for JSONP, served as
+    /// `application/javascript`, or leaves it as it is for `None`.
+    ///
+    /// The callback usually comes from the query string, so it has to be a
+    /// JavaScript identifier or a path of them, like `jQuery123.done`. Other
+    /// names get `400 Bad Request` instead of being sent back.
+    pub fn jsonp<S: Into<String>>(mut self, callback: Option<S>) -> Self {
+        self.callback = callback.map(Into::into);
+        self
+    }
+}
+
+fn is_callback(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 128 && name.split('.').all(|part| {
+        let mut chars = part.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+    })
+}
+
+impl<D, T> Responder<D> for Json<T>
+where D: Send + 'static + Sync,
+      T: Serialize {
+    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
+        if let Some(ref callback) = self.callback {
+            if !is_callback(callback) {
+                return res.error(StatusCode::BAD_REQUEST, format!("Invalid JSONP callback '{}'", callback));
+            }
+        }
+
+        let serialized = if self.pretty {
+            serde_json::to_vec_pretty(&self.value)
+        } else {
+            serde_json::to_vec(&self.value)
+        };
+        let json = match serialized {
+            Ok(json) => json,
+            Err(e) => return res.error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize JSON: {}", e))
+        };
+
+        let (content_type, body) = match self.callback {
+            None => ("application/json; charset=utf-8", json),
+            Some(callback) => {
+                // the line separators are valid in JSON strings, but not in
+                // older JavaScript ones
+                let json = String::from_utf8(json).unwrap()
+                    .replace('\u{2028}', "\\u2028")
+                    .replace('\u{2029}', "\\u2029");
+                let body = format!("/**/{}({});", callback, json);
+                ("application/javascript; charset=utf-8", body.into_bytes())
+            }
+        };
+        res.set_header_fallback(&header::CONTENT_TYPE, &HeaderValue::from_static(content_type));
+        res.start();
+        res.set_body(body);
+        Ok(Halt(res))
+    }
+}
+
+#[tokio::test]
+async fn responds_with_json() {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use hyper::body;
+    use crate::test_util::response;
+
+    async fn send<T: Serialize>(json: Json<T>) -> (StatusCode, String, String) {
+        let res = match response(Arc::new(())).send(json) {
+            Ok(Halt(res)) => res,
+            Err(err) => err.stream.unwrap(),
+            _ => panic!("expected the response to halt")
+        };
+        let (parts, body) = res.origin.into_parts();
+        let content_type = parts.headers.get(header::CONTENT_TYPE).map_or("", |h| h.to_str().unwrap()).to_string();
+        (parts.status, content_type, String::from_utf8(body::to_bytes(body).await.unwrap().to_vec()).unwrap())
+    }
+
+    let (_, content_type, body) = send(Json::new(vec![1, 2])).await;
+    assert_eq!((&content_type[..], &body[..]), ("application/json; charset=utf-8", "[1,2]"));
+    assert_eq!(send(Json::new(vec![1, 2]).pretty()).await.2, "[\n  1,\n  2\n]");
+
+    let (_, content_type, body) = send(Json::new("a\u{2028}b").jsonp(Some("jQuery1.done"))).await;
+    assert_eq!(content_type, "application/javascript; charset=utf-8");
+    assert_eq!(body, "/**/jQuery1.done(\"a\\u2028b\");");
+    assert_eq!(send(Json::new(1).jsonp(None::<String>)).await.2, "1");
+    assert_eq!(send(Json::new(1).jsonp(Some("alert(1);f"))).await.0, StatusCode::BAD_REQUEST);
+
+    let mut unserializable = HashMap::new();
+    unserializable.insert(vec![1], 1);
+    assert_eq!(send(Json::new(unserializable)).await.0, StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -8,18 +8,28 @@ pub use hyper;
 pub use crate::nickel::{Nickel, Options};
 pub use crate::request::Request;
 pub use crate::response::Response;
+pub use crate::response_writer::ResponseWriter;
 pub use crate::middleware::{Action, Continue, Halt, Middleware, ErrorHandler, MiddlewareResult};
 pub use crate::static_files_handler::StaticFilesHandler;
-pub use crate::mount::{Mount, Mountable};
+pub use crate::mount::{Mount, Mountable, MountError};
+pub use crate::versioned::Versioned;
+pub use crate::virtual_hosts::VirtualHosts;
+pub use crate::rewrite::{Rewrite, Rule, RewriteError};
+pub use crate::normalize_path::{NormalizePath, TrailingSlash};
 pub use crate::favicon_handler::FaviconHandler;
 pub use crate::default_error_handler::DefaultErrorHandler;
+pub use crate::problem::{Problem, ProblemErrorHandler};
+pub use crate::negotiated_error_handler::NegotiatedErrorHandler;
 //pub use crate::body_parser::{BodyError, FormBody, JsonBody};
 pub use crate::query_string::QueryString;
 pub use crate::urlencoded::{Params, Query};
-pub use crate::router::{Router, Route, RouteResult, HttpRouter};
+pub use crate::router::{Router, Route, RouteResult, HttpRouter, RouteInfo, RouteConflict, Resource, ResourceAction};
 pub use crate::nickel_error::NickelError;
 pub use crate::mimes::MediaType;
-pub use crate::responder::Responder;
+pub use crate::responder::{Responder, ResponseHeaders};
+pub use crate::json::Json;
+pub use crate::negotiation::Negotiate;
+pub use crate::batch::Batch;
 pub use crate::template_cache::{ReloadPolicy, TemplateCache};
 
 #[macro_use] pub mod macros;
@@ -29,11 +39,20 @@ mod server;
 mod nickel;
 mod request;
 mod response;
+mod response_writer;
+mod send_file;
 mod middleware;
 mod responder;
+mod json;
+mod negotiation;
+mod batch;
 mod favicon_handler;
 mod static_files_handler;
 mod mount;
+mod versioned;
+mod virtual_hosts;
+mod rewrite;
+mod normalize_path;
 
 // WARNING: this module is no longer used, and is only being kept around for
 // documentation as part of migration to async.
@@ -44,9 +63,18 @@ pub mod mimes;
 mod urlencoded;
 mod nickel_error;
 mod default_error_handler;
+mod problem;
+mod negotiated_error_handler;
 pub mod extensions;
+pub mod sse;
+pub mod websocket;
+pub mod hub;
+pub mod attachment;
 pub mod template_cache;
 
+#[cfg(test)]
+mod test_util;
+
 pub mod status {
     pub use hyper::StatusCode;
 }
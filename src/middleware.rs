@@ -2,7 +2,8 @@ use async_trait::async_trait;
 use crate::request::Request;
 use crate::response::Response;
 use crate::nickel_error::NickelError;
-use hyper::{Body, Response as HyperResponse};
+use crate::router::RouteInfo;
+use hyper::{Body, Response as HyperResponse, StatusCode};
 
 pub use self::Action::{Continue, Halt};
 
@@ -21,6 +22,12 @@ pub trait Middleware<D: Send + 'static + Sync>: Send + 'static + Sync {
     async fn invoke(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
         res.next_middleware()
     }
+
+    /// The routes this middleware dispatches to, in matching order. Used to
+    /// build the route table, see `Nickel::routes`.
+    fn routes(&self) -> Vec<RouteInfo> {
+        Vec::new()
+    }
 }
 
 #[async_trait]
@@ -43,6 +50,70 @@ impl<D: Send + 'static + Sync> ErrorHandler<D> for fn(&mut NickelError<D>, &mut
     }
 }
 
+/// A not found handler and error handlers which only apply to part of the
+/// middleware tree, e.g. the requests handled by one `Router`. Errors not
+/// handled here are passed on to the global error handlers.
+pub(crate) struct LocalHandlers<D> {
+    not_found: Option<Box<dyn Middleware<D> + Send + Sync>>,
+    error_handlers: Vec<Box<dyn ErrorHandler<D> + Send + Sync>>
+}
+
+impl<D> LocalHandlers<D> {
+    pub fn new() -> LocalHandlers<D> {
+        LocalHandlers {
+            not_found: None,
+            error_handlers: Vec::new()
+        }
+    }
+}
+
+impl<D: Send + 'static + Sync> LocalHandlers<D> {
+    pub fn set_not_found<T: Middleware<D>>(&mut self, handler: T) {
+        self.not_found = Some(Box::new(handler));
+    }
+
+    pub fn add_error_handler<T: ErrorHandler<D>>(&mut self, handler: T) {
+        self.error_handlers.push(Box::new(handler));
+    }
+
+    pub fn has_not_found(&self) -> bool {
+        self.not_found.is_some()
+    }
+
+    /// Invokes the not found handler, if any, with a 404 status already set.
+    pub async fn not_found(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        match self.not_found {
+            Some(ref handler) => {
+                res.set(StatusCode::NOT_FOUND);
+                handler.invoke(req, res).await
+            },
+            None => res.next_middleware()
+        }
+    }
+
+    /// Gives the error handlers a chance to turn an error into a response,
+    /// newest handler first, like `MiddlewareStack` does.
+    // the error of a handler carries the response back, large as it is
+    #[allow(clippy::result_large_err)]
+    pub fn handle_errors(&self, result: MiddlewareResult<D>, req: &mut Request<D>) -> MiddlewareResult<D> {
+        let mut err = match result {
+            Err(err) => err,
+            ok => return ok
+        };
+
+        for error_handler in self.error_handlers.iter().rev() {
+            if let Halt(()) = error_handler.handle_error(&mut err, req) {
+                if let Some(res) = err.stream.take() {
+                    return Ok(Halt(res));
+                }
+                break;
+            }
+        }
+
+        Err(err)
+    }
+}
+
 pub struct MiddlewareStack<D: Send + 'static + Sync = ()> {
     handlers: Vec<Box<dyn Middleware<D> + Send + Sync>>,
     error_handlers: Vec<Box<dyn ErrorHandler<D> + Send + Sync>>
@@ -57,9 +128,27 @@ impl<D: Send + 'static + Sync> MiddlewareStack<D> {
         self.error_handlers.push(Box::new(handler));
     }
 
-    pub async fn invoke(&self, mut req: Request<D>, mut res: Response<D>) -> HyperResponse<Body> {
+    /// Adds a middleware in front of all others.
+    pub fn prepend_middleware<T: Middleware<D>> (&mut self, handler: T) {
+        self.handlers.insert(0, Box::new(handler));
+    }
+
+    /// All routes of all middleware, in the order they are matched.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.handlers.iter().flat_map(|h| h.routes()).collect()
+    }
+
+    pub async fn invoke(&self, mut req: Request<D>, res: Response<D>) -> HyperResponse<Body> {
+        match self.dispatch(&mut req, res).await {
+            Halt(res) | Continue(res) => res.origin
+        }
+    }
+
+    /// Runs the request through the middleware and error handlers. Returns
+    /// `Continue` with the last response if no middleware halted.
+    pub(crate) async fn dispatch(&self, req: &mut Request<D>, mut res: Response<D>) -> Action<Response<D>> {
         for handler in self.handlers.iter() {
-            match handler.invoke(&mut req, res).await {
+            match handler.invoke(req, res).await {
                 Ok(Halt(res)) => {
                     debug!("Halted {:?} {:?} {:?} {:?}",
                            req.origin.method(),
@@ -67,7 +156,7 @@ impl<D: Send + 'static + Sync> MiddlewareStack<D> {
                            req.origin.uri(),
                            res.status());
                     // let _ = res.end();
-                    return res.origin;
+                    return Halt(res);
                 },
                 Ok(Continue(fresh)) => res = fresh,
                 Err(mut err) => {
@@ -79,9 +168,9 @@ impl<D: Send + 'static + Sync> MiddlewareStack<D> {
                           err.stream.as_ref().map(|s| s.status()));
 
                     for error_handler in self.error_handlers.iter().rev() {
-                        if let Halt(()) = error_handler.handle_error(&mut err, &mut req) {
+                        if let Halt(()) = error_handler.handle_error(&mut err, req) {
                             if let Some(res) = err.stream {
-                                return res.origin;
+                                return Halt(res);
                             } else {
                                 error!("Error without Response struct");
                                 // Create a new Response with an InternalServerError
@@ -102,7 +191,7 @@ impl<D: Send + 'static + Sync> MiddlewareStack<D> {
             }
         }
         // No middleware returned Halt, go with the last one in the train
-        res.origin // Todo: migration cleanup - return 404
+        Continue(res) // Todo: migration cleanup - return 404
     }
 
     pub fn new () -> MiddlewareStack<D> {
@@ -285,6 +285,7 @@ mimes!(
         Tpl, "tpl", "vnd.groove-tool-template",
         Vcg, "vcg", "vnd.groove-vcard",
         Hal, "hal", "hal+json",
+        ProblemJson, "problem", "problem+json",
         HalXml, "halxml", "hal+xml",
         HalVnd, "halvnd", "vnd.hal+xml",
         Zmm, "zmm", "vnd.handheld-entertainment+xml",
@@ -784,6 +785,7 @@ mimes!(
         Ics, "ics", "calendar",
         Css, "css", "css",
         Csv, "csv", "csv",
+        EventStream, "event-stream", "event-stream",
         Html, "html", "html",
         N3, "n3", "n3",
         Txt, "txt", "plain",
@@ -1,12 +1,16 @@
+use std::error::Error as StdError;
+use std::fmt;
 use async_trait::async_trait;
+use regex::Regex;
 use crate::nickel::Nickel;
-use crate::request::Request;
+use crate::request::{Request, MountState};
 use crate::response::Response;
-use crate::middleware::{Continue, Middleware, MiddlewareResult};
+use crate::middleware::{Continue, Middleware, MiddlewareResult, ErrorHandler, LocalHandlers};
+use crate::router::{RouteInfo, compile_prefix};
 use hyper::Uri;
 
 pub trait Mountable<D: Send + 'static + Sync>: Send + 'static + Sync {
-    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M);
+    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M) -> Result<(), MountError>;
 }
 
 impl<D> Mountable<D> for Nickel<D>
@@ -20,22 +24,40 @@ where D: Send + Sync + 'static {
     /// use nickel::{Nickel, StaticFilesHandler, Mountable};
     /// let mut server = Nickel::new();
     ///
-    /// server.mount("/static_files/", StaticFilesHandler::new("/path/to/serve/"));
+    /// server.mount("/static_files/", StaticFilesHandler::new("/path/to/serve/")).unwrap();
     /// ```
     ///
-    /// # Panics
-    /// Panics if mount_point does not have a leading and trailing slash.
-    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M) {
-        self.utilize(Mount::new(mount_point, middleware));
+    /// # Errors
+    /// Fails if the mount point is invalid, see `Mount::new`.
+    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M) -> Result<(), MountError> {
+        self.utilize(Mount::new(mount_point, middleware)?);
+        Ok(())
     }
 }
 
-pub struct Mount<M> {
+/// The error returned for an invalid mount point.
+#[derive(Debug)]
+pub struct MountError {
     mount_point: String,
-    middleware: M
+    reason: String
 }
 
-impl<M> Mount<M> {
+impl fmt::Display for MountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid mount point '{}': {}", self.mount_point, self.reason)
+    }
+}
+
+impl StdError for MountError { }
+
+pub struct Mount<M, D = ()> {
+    mount_point: String,
+    regex: Regex,
+    middleware: M,
+    handlers: LocalHandlers<D>
+}
+
+impl<M, D> Mount<M, D> {
     ///
     /// Creates a new middleware that mounts a middleware at a mount point.
     /// An incoming request that matches the mount point will be forwareded to
@@ -44,6 +66,11 @@ impl<M> Mount<M> {
     /// middleware. This can be useful in combination with the
     /// `StaticFilesMiddleware`, for example.
     ///
+    /// The mount point may contain params and wildcards like a route, their
+    /// values are available through `Request::param`. The stripped part of
+    /// the path is available through `Request::base_path` and the path as
+    /// requested through `Request::original_uri`.
+    ///
     ///
     /// # Examples
     /// ```{rust}
@@ -53,42 +80,264 @@ impl<M> Mount<M> {
     /// server.utilize(
     ///     Mount::new("/static_files/",
     ///                StaticFilesHandler::new("/path/to/serve/")
-    /// ));
+    /// ).unwrap());
+    ///
+    /// server.utilize(
+    ///     Mount::new("/users/:user/files/",
+    ///                StaticFilesHandler::new("/path/to/serve/")
+    /// ).unwrap());
     /// ```
     ///
-    /// # Panics
-    /// Panics if mount_point does not have a leading and trailing slash.
-    pub fn new<S: Into<String>>(mount_point: S, middleware: M) -> Mount<M> {
-        let mount_point: String = mount_point.into();
-        match (mount_point.chars().last(), mount_point.chars().nth(0)) {
-            (Some('/'), Some('/')) =>
-                Mount {
-                    mount_point: mount_point,
-                    middleware: middleware
-                },
-            _ => panic!("Mount points must have a leading and trailing slash.")
+    /// # Errors
+    /// Fails if mount_point does not have a leading slash, or has params
+    /// which don't make a valid pattern. A missing trailing slash is added.
+    pub fn new<S: Into<String>>(mount_point: S, middleware: M) -> Result<Mount<M, D>, MountError> {
+        let mut mount_point: String = mount_point.into();
+        let error = |mount_point: &str, reason: String| MountError {
+            mount_point: mount_point.to_string(),
+            reason
+        };
+
+        if !mount_point.starts_with('/') {
+            return Err(error(&mount_point, "mount points must have a leading slash".to_string()));
         }
+        if !mount_point.ends_with('/') {
+            mount_point.push('/');
+        }
+
+        // The last group captures the path left for the mounted middleware
+        let pattern = format!("^{}(/.*)$", compile_prefix(mount_point.trim_end_matches('/')));
+        let regex = Regex::new(&pattern).map_err(|e| error(&mount_point, e.to_string()))?;
+
+        Ok(Mount {
+            mount_point,
+            regex,
+            middleware,
+            handlers: LocalHandlers::new()
+        })
     }
 }
 
+impl<M, D: Send + 'static + Sync> Mount<M, D> {
+    /// Registers a handler for requests under the mount point which the
+    /// mounted middleware doesn't respond to, instead of passing them on to
+    /// the next middleware. The response status is already set to
+    /// `404 Not Found` when it is invoked.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, StaticFilesHandler, Mount};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///
+    ///     server.utilize(
+    ///         Mount::new("/static_files/", StaticFilesHandler::new("/path/to/serve/"))
+    ///             .unwrap()
+    ///             .not_found(middleware! { "No such file" })
+    ///     );
+    /// }
+    /// ```
+    pub fn not_found<T: Middleware<D>>(mut self, handler: T) -> Self {
+        self.handlers.set_not_found(handler);
+        self
+    }
+
+    /// Registers an error handler for errors returned by the mounted
+    /// middleware. Errors which none of the mount's error handlers handle are
+    /// passed on to the global error handlers.
+    pub fn handle_error<T: ErrorHandler<D>>(mut self, handler: T) -> Self {
+        self.handlers.add_error_handler(handler);
+        self
+    }
+}
+
+impl<M, D> Mount<M, D> {
+    // Matches the mount point, returning the uri the mounted middleware sees
+    // and the state recorded on the request.
+    fn strip(&self, req: &Request<D>) -> Option<(Uri, MountState)> {
+        strip_prefix(&self.regex, req)
+    }
+}
+
+// Replaces the path and query of `uri`.
+pub(crate) fn with_path(uri: &Uri, path: &str, query: Option<&str>) -> Option<Uri> {
+    let paq = match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string()
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(paq.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+// The mount state of `req`, or a fresh one recording its current uri as the
+// original one.
+pub(crate) fn mount_state<D>(req: &Request<D>) -> MountState {
+    req.mounted.clone().unwrap_or_else(|| MountState {
+        original_uri: req.origin.uri().clone(),
+        base_path: String::new(),
+        params: Vec::new()
+    })
+}
+
+// Strips the prefix matched by `regex`, whose last group has to capture the
+// rest of the path. Named groups become params of the request.
+pub(crate) fn strip_prefix<D>(regex: &Regex, req: &Request<D>) -> Option<(Uri, MountState)> {
+    let path = req.origin.uri().path();
+    let captures = regex.captures(path)?;
+    let rest = captures.get(captures.len() - 1)?;
+
+    let new_uri = with_path(req.origin.uri(), rest.as_str(), req.origin.uri().query())?;
+
+    let mut mounted = mount_state(req);
+    mounted.base_path.push_str(&path[..rest.start()]);
+    mounted.params.extend(regex.capture_names().flatten().filter_map(|name| {
+        captures.name(name).map(|c| (name.to_string(), c.as_str().to_string()))
+    }));
+
+    Some((new_uri, mounted))
+}
+
 #[async_trait]
-impl<D: Send + 'static + Sync, M: Middleware<D>> Middleware<D> for Mount<M> {
+impl<D: Send + 'static + Sync, M: Middleware<D>> Middleware<D> for Mount<M, D> {
     async fn invoke(&self, req: &mut Request<D>, res: Response<D>)
                           -> MiddlewareResult<D> {
-        // two clones in this method, there ought to be a way to avoid that
-        let mut parts = req.origin.uri().clone().into_parts();
-        match req.origin.uri().path_and_query() {
-            Some(paq) if paq.as_str().starts_with(&*self.mount_point) => {
-                let new_paq_str = format!("/{}", &paq.as_str()[self.mount_point.len()..]);
-                parts.path_and_query = Some(new_paq_str.parse().expect("TODO: can this ever fail?"));
-            },
-            _ => { return Ok(Continue(res)); }
+        let (new_uri, mounted) = match self.strip(req) {
+            Some(stripped) => stripped,
+            None => { return Ok(Continue(res)); }
         };
-        
+
         let original = req.origin.uri().clone();
-        *req.origin.uri_mut() = Uri::from_parts(parts).expect("TODO: can this ever fail?");
-        let result = self.middleware.invoke(req, res).await;
+        let outer = req.mounted.replace(mounted);
+        // `NormalizePath` may turn it on for the mounted middleware only
+        let ignore_case = req.ignore_case;
+        *req.origin.uri_mut() = new_uri;
+        let result = match self.middleware.invoke(req, res).await {
+            Ok(Continue(res)) => self.handlers.not_found(req, res).await,
+            result => result
+        };
+        let result = self.handlers.handle_errors(result, req);
         *req.origin.uri_mut() = original;
+        req.mounted = outer;
+        req.ignore_case = ignore_case;
         result
     }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.middleware.routes().into_iter()
+            .map(|route| route.with_prefix(&self.mount_point))
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn mount_not_found_and_error_handlers() {
+    use hyper::{Method, StatusCode};
+    use crate::{Action, Halt, HttpRouter, NickelError, Router};
+    use crate::test_util::run;
+
+    fn plain_errors(err: &mut NickelError, _: &mut Request) -> Action {
+        if let Some(ref mut res) = err.stream {
+            res.set_body(format!("mounted: {}", err.message));
+        }
+        Halt(())
+    }
+
+    fn mount() -> Mount<Router> {
+        let mut router = Router::new();
+        router.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad input") });
+        let handler: fn(&mut NickelError, &mut Request) -> Action = plain_errors;
+        Mount::new("/app/", router).unwrap()
+            .not_found(middleware! { |request| format!("no {}", request.path_without_query()) })
+            .handle_error(handler)
+    }
+
+    assert_eq!(run(mount(), Method::GET, "/app/nope").await, (StatusCode::NOT_FOUND, "no /nope".to_string()));
+    assert_eq!(run(mount(), Method::GET, "/app/fail").await,
+               (StatusCode::BAD_REQUEST, "mounted: bad input".to_string()));
+    assert_eq!(run(mount(), Method::GET, "/nope").await, (StatusCode::NOT_FOUND, "".to_string()));
+}
+
+#[test]
+fn rejects_invalid_mount_points() {
+    fn mounted(_: &mut Request, res: Response) -> MiddlewareResult {
+        res.send("mounted")
+    }
+
+    assert!(Mount::<_, ()>::new("static/", mounted).is_err());
+    assert!(Mount::<_, ()>::new("/static", mounted).is_ok());
+    assert!(Mount::<_, ()>::new("/:/", mounted).is_err());
+    assert!(Mount::<_, ()>::new("/a(b)/", mounted).is_ok());
+}
+
+#[tokio::test]
+async fn mount_points_are_literal() {
+    use hyper::Method;
+    use crate::test_util::run;
+
+    let mount = |mount_point: &str| {
+        Mount::new(mount_point, middleware! { |request| request.path_without_query().to_string() }).unwrap()
+    };
+
+    assert_eq!(run(mount("/v1.0/"), Method::GET, "/v1.0/users").await.1, "/users");
+    assert_eq!(run(mount("/v1.0/"), Method::GET, "/v1x0/users").await.1, "");
+    assert_eq!(run(mount("/c++/"), Method::GET, "/c++/docs").await.1, "/docs");
+    assert_eq!(run(mount("/c++/"), Method::GET, "/cc/docs").await.1, "");
+}
+
+#[tokio::test]
+async fn mount_records_base_path_and_params() {
+    use hyper::Method;
+    use crate::test_util::run;
+
+    let inner = middleware! { |request|
+        format!("{} {} {} {}", request.origin.uri(), request.original_uri(),
+                request.base_path(), request.param("team").unwrap())
+    };
+    let outer = Mount::new("/orgs/:org/", Mount::new("/teams/:team", inner).unwrap()).unwrap();
+
+    assert_eq!(run(outer, Method::GET, "/orgs/acme/teams/ops/members?page=2").await.1,
+               "/members?page=2 /orgs/acme/teams/ops/members?page=2 /orgs/acme/teams/ops ops");
+
+    let mounted = middleware! { |request| request.param("org").unwrap().to_string() };
+    let mount = Mount::new("/orgs/:org/", mounted).unwrap();
+    assert_eq!(run(mount, Method::GET, "/orgs/acme/").await.1, "acme");
+}
+
+#[tokio::test]
+async fn mounts_sub_apps_with_own_data() {
+    use std::sync::Arc;
+    use hyper::{Method, StatusCode};
+    use crate::{Action, Halt, HttpRouter, NickelError};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_stack};
+
+    struct Billing { currency: &'static str }
+
+    fn billing_errors(err: &mut NickelError<Billing>, _: &mut Request<Billing>) -> Action {
+        if let Some(ref mut res) = err.stream {
+            let currency = res.server_data().currency;
+            res.set_body(format!("billing ({}): {}", currency, err.message));
+        }
+        Halt(())
+    }
+
+    let mut billing = Nickel::with_data(Billing { currency: "EUR" });
+    billing.get("/invoices", middleware! { |request, response| <Billing>
+        format!("{} in {}", request.base_path(), response.server_data().currency)
+    });
+    billing.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad invoice") });
+    let handler: fn(&mut NickelError<Billing>, &mut Request<Billing>) -> Action = billing_errors;
+    billing.handle_error(handler);
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(Mount::new("/billing/", billing).unwrap());
+    stack.add_middleware(middleware! { |request| format!("outer {}", request.path_without_query()) });
+
+    let run = |uri| run_stack(&stack, request(Method::GET, uri, Arc::new(())));
+    assert_eq!(run("/billing/invoices").await, (StatusCode::OK, "/billing in EUR".to_string()));
+    assert_eq!(run("/billing/fail").await, (StatusCode::BAD_REQUEST, "billing (EUR): bad invoice".to_string()));
+    assert_eq!(run("/billing/nope").await.1, "outer /billing/nope");
 }
@@ -0,0 +1,171 @@
+//! Error responses in the representation the client asks for, with HTML
+//! pages rendered from templates.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures::stream;
+use hyper::{Body, StatusCode};
+use hyper::header::{self, HeaderValue};
+use serde_json::{json, Value};
+use crate::middleware::{Action, ErrorHandler, Halt};
+use crate::mimes::MediaType;
+use crate::nickel_error::NickelError;
+use crate::request::Request;
+use crate::template_cache::TemplateCache;
+
+// In order of preference, browsers get HTML and API clients JSON.
+const OFFERED: [MediaType; 4] = [MediaType::Html, MediaType::ProblemJson, MediaType::Json, MediaType::Txt];
+
+/// An error handler responding in the format the client accepts, HTML for
+/// browsers and JSON for API clients, picked with `Request::negotiate`.
+///
+/// HTML pages are rendered from mustache templates in a directory, `errors`
+/// by default: `404.html` for a `404 Not Found`, with `4xx.html` as the
+/// fallback for the other client errors and `5xx.html` for the server
+/// errors. The templates get the `status`, its `reason`, the `message` of
+/// the error and the `path` of the request. Without a template, a bare page
+/// is sent.
+///
+/// JSON is the `Problem` of the error, see `ProblemErrorHandler`, and
+/// clients accepting neither get the reason as text.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, NegotiatedErrorHandler};
+///
+/// let mut server = Nickel::new();
+/// server.handle_error(NegotiatedErrorHandler::new().templates("views/errors"));
+/// ```
+///
+/// with `views/errors/404.html` like
+///
+/// ```text
+/// <h1>{{status}} {{reason}}</h1>
+/// <p>Nothing lives at <code>{{path}}</code>.</p>
+/// ```
+#[derive(Clone)]
+pub struct NegotiatedErrorHandler {
+    templates: PathBuf,
+}
+
+impl NegotiatedErrorHandler {
+    /// A handler with its templates in `errors`.
+    pub fn new() -> NegotiatedErrorHandler {
+        NegotiatedErrorHandler { templates: PathBuf::from("errors") }
+    }
+
+    /// Sets the directory of the templates.
+    pub fn templates<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.templates = dir.into();
+        self
+    }
+}
+
+impl Default for NegotiatedErrorHandler {
+    fn default() -> Self {
+        NegotiatedErrorHandler::new()
+    }
+}
+
+// The page from the first template of `status` which renders, or a bare one.
+async fn render_page(templates: Arc<TemplateCache>, dir: &Path, status: StatusCode, data: Value) -> String {
+    let status = status.as_u16();
+    let candidates = [format!("{}.html", status), format!("{}xx.html", status / 100)];
+    for name in &candidates {
+        let path = dir.join(name);
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            continue;
+        }
+        match templates.render(&path, &data).await {
+            Ok(page) => return page,
+            Err(e) => error!("Failed to render error page {:?}: {:?}", path, e)
+        }
+    }
+    let reason = data["reason"].as_str().unwrap_or_default();
+    format!("<!DOCTYPE html>\n<title>{0} {1}</title>\n<h1>{0} {1}</h1>\n", status, reason)
+}
+
+impl<D: Send + 'static + Sync> ErrorHandler<D> for NegotiatedErrorHandler {
+    fn handle_error(&self, err: &mut NickelError<D>, req: &mut Request<D>) -> Action {
+        let problem = err.problem().clone();
+        let res = match err.stream {
+            Some(ref mut res) => res,
+            None => {
+                error!("Error: {}", err.message);
+                return Halt(());
+            }
+        };
+
+        let status = res.status();
+        let reason = status.canonical_reason().unwrap_or("Error");
+        let media_type = req.negotiate(&OFFERED).unwrap_or(MediaType::Txt);
+        res.set_header(header::CONTENT_TYPE, media_type);
+        res.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+        res.headers_mut().remove(header::CONTENT_LENGTH);
+
+        match media_type {
+            MediaType::Html => {
+                let data = json!({
+                    "status": status.as_u16(),
+                    "reason": reason,
+                    "message": err.message,
+                    "path": req.path_without_query()
+                });
+                let (templates, dir) = (res.templates(), self.templates.clone());
+                // error handlers can't wait, the page is rendered as the
+                // body is sent
+                let page = stream::once(async move {
+                    Ok::<_, io::Error>(render_page(templates, &dir, status, data).await)
+                });
+                res.set_body(Body::wrap_stream(page));
+            },
+            MediaType::Json | MediaType::ProblemJson => res.set_body(problem.to_json(status).to_string()),
+            _ => res.set_body(reason)
+        }
+
+        Halt(())
+    }
+}
+
+#[tokio::test]
+async fn negotiates_error_pages() {
+    use hyper::Method;
+    use crate::{HttpRouter, MiddlewareResult, Response, Router};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_stack};
+
+    let dir = std::env::temp_dir().join(format!("nickel-error-pages-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("404.html"), "<p>{{status}} {{reason}}: {{path}} {{message}}</p>").unwrap();
+    std::fs::write(dir.join("5xx.html"), "<p>Sorry, {{status}}</p>").unwrap();
+
+    fn failing(_: &mut Request<()>, res: Response<()>) -> MiddlewareResult<()> {
+        res.error(StatusCode::SERVICE_UNAVAILABLE, "down")
+    }
+    let mut router = Router::new();
+    router.get("/failing", failing);
+    router.get("/missing", |_: &mut Request<()>, res: Response<()>| res.error(StatusCode::NOT_FOUND, "<gone>"));
+    router.get("/teapot", |_: &mut Request<()>, res: Response<()>| res.error(StatusCode::IM_A_TEAPOT, "tea"));
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(NegotiatedErrorHandler::new().templates(&dir));
+    stack.add_middleware(router);
+
+    let run = |path: &'static str, accept: &'static str| {
+        let mut req = request(Method::GET, path, Arc::new(()));
+        req.origin.headers_mut().insert(header::ACCEPT, HeaderValue::from_static(accept));
+        run_stack(&stack, req)
+    };
+    let browser = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+    assert_eq!(run("/missing", browser).await, (StatusCode::NOT_FOUND, "<p>404 Not Found: /missing &lt;gone&gt;</p>".to_string()));
+    assert_eq!(run("/failing", browser).await.1, "<p>Sorry, 503</p>");
+    assert_eq!(run("/teapot", browser).await.1, "<!DOCTYPE html>\n<title>418 I'm a teapot</title>\n<h1>418 I'm a teapot</h1>\n");
+
+    let json = run("/missing", "application/json").await.1;
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+               serde_json::json!({ "type": "about:blank", "title": "Not Found", "status": 404 }));
+    assert_eq!(run("/failing", "text/plain").await.1, "Service Unavailable");
+    assert_eq!(run("/failing", "image/png").await.1, "Service Unavailable");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,243 @@
+//! Content negotiation, picking a representation from the `:format` param
+//! or the `Accept` header.
+use std::str::FromStr;
+use async_trait::async_trait;
+use hyper::StatusCode;
+use hyper::header::{self, HeaderValue};
+use mime::Mime;
+use serde::Serialize;
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::responder::Responder;
+use crate::response::Response;
+use crate::router::FORMAT_PARAM;
+
+impl<D> Request<D> {
+    /// Picks the media type to respond with out of `offered`, which is in
+    /// order of preference.
+    ///
+    /// An extension matched by the route's `:format` param (e.g. `.json`)
+    /// decides on its own, otherwise the `Accept` header is used, honouring
+    /// q-values. Without an `Accept` header the first offered type is used.
+    /// Returns `None` if nothing offered is acceptable.
+    pub fn negotiate(&self, offered: &[MediaType]) -> Option<MediaType> {
+        match self.param(FORMAT_PARAM) {
+            Some(format) if !format.is_empty() => {
+                MediaType::from_str(format).ok().filter(|mt| offered.contains(mt))
+            },
+            _ => negotiate_accept(self.origin.headers().get_all(header::ACCEPT).iter(), offered)
+        }
+    }
+}
+
+fn negotiate_accept<'a, I>(accept: I, offered: &[MediaType]) -> Option<MediaType>
+where I: Iterator<Item = &'a HeaderValue> {
+    let ranges: Vec<(Mime, f32)> = accept
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| range.trim().parse::<Mime>().ok())
+        .map(|range| {
+            let q = range.get_param("q").and_then(|q| q.as_str().parse().ok()).unwrap_or(1.0);
+            (range, q)
+        })
+        .collect();
+
+    if ranges.is_empty() {
+        return offered.first().cloned();
+    }
+
+    let mut best: Option<(MediaType, f32)> = None;
+    for &media_type in offered {
+        let q = quality(&Mime::from(media_type), &ranges);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+// The q-value of the most specific range matching `mime`.
+fn quality(mime: &Mime, ranges: &[(Mime, f32)]) -> f32 {
+    ranges.iter().filter_map(|(range, q)| {
+        let specificity = if range.type_() == mime::STAR {
+            0
+        } else if range.type_() != mime.type_() {
+            return None
+        } else if range.subtype() == mime::STAR {
+            1
+        } else if range.subtype() == mime.subtype() {
+            2
+        } else {
+            return None
+        };
+        Some((specificity, *q))
+    }).max_by_key(|&(specificity, _)| specificity).map_or(0.0, |(_, q)| q)
+}
+
+type RenderFn<D> = Box<dyn Fn(&mut Request<D>, Response<D>) -> MiddlewareResult<D> + Send + Sync>;
+type TemplateDataFn<D> = Box<dyn Fn(&mut Request<D>) -> Result<serde_json::Value, serde_json::Error> + Send + Sync>;
+
+enum Render<D: Send + 'static + Sync> {
+    Handler(RenderFn<D>),
+    Template(String, TemplateDataFn<D>),
+}
+
+/// A handler which responds with one of several representations, picked
+/// with `Request::negotiate`.
+///
+/// Formats are registered in order of preference. Requests accepting none
+/// of them get a `406 Not Acceptable` error, and all responses carry a
+/// `Vary: Accept` header.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate serde_json;
+/// use nickel::{Nickel, HttpRouter, Negotiate};
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///
+///     // `/users/42`, `/users/42.json` or `/users/42.html`
+///     server.get("/users/:id", Negotiate::new()
+///         .json(|request| json!({ "id": request.param("id") }))
+///         .html("examples/assets/template.tpl", |request| json!({ "name": request.param("id") }))
+///         .text(|request| format!("user {}", request.param("id").unwrap())));
+/// }
+/// ```
+pub struct Negotiate<D: Send + 'static + Sync = ()> {
+    formats: Vec<(MediaType, Render<D>)>,
+}
+
+impl<D: Send + 'static + Sync> Negotiate<D> {
+    pub fn new() -> Negotiate<D> {
+        Negotiate { formats: Vec::new() }
+    }
+
+    /// Responds to requests for `media_type` with whatever `render` returns.
+    /// The content type is set before `render` is called.
+    pub fn format<F, R>(mut self, media_type: MediaType, render: F) -> Self
+    where F: Fn(&mut Request<D>, &mut Response<D>) -> R + Send + Sync + 'static,
+          R: Responder<D> {
+        // the error of a handler carries the response back, large as it is
+        #[allow(clippy::result_large_err)]
+        let render = move |req: &mut Request<D>, mut res: Response<D>| {
+            let body = render(req, &mut res);
+            res.send(body)
+        };
+        self.formats.push((media_type, Render::Handler(Box::new(render))));
+        self
+    }
+
+    /// Responds with `data` serialized as JSON.
+    pub fn json<F, T>(self, data: F) -> Self
+    where F: Fn(&mut Request<D>) -> T + Send + Sync + 'static,
+          T: Serialize {
+        self.format(MediaType::Json, move |req, _| {
+            serde_json::to_string(&data(req)).map_err(|e| format!("Failed to serialize JSON: {}", e))
+        })
+    }
+
+    /// Responds with the template at `path` rendered with `data`, see
+    /// `Response::render`.
+    pub fn html<P, F, T>(mut self, path: P, data: F) -> Self
+    where P: Into<String>,
+          F: Fn(&mut Request<D>) -> T + Send + Sync + 'static,
+          T: Serialize {
+        let data = move |req: &mut Request<D>| serde_json::to_value(data(req));
+        self.formats.push((MediaType::Html, Render::Template(path.into(), Box::new(data))));
+        self
+    }
+
+    /// Responds with plain text.
+    pub fn text<F, T>(self, text: F) -> Self
+    where F: Fn(&mut Request<D>) -> T + Send + Sync + 'static,
+          T: Responder<D> {
+        self.format(MediaType::Txt, move |req, _| text(req))
+    }
+}
+
+impl<D: Send + 'static + Sync> Default for Negotiate<D> {
+    fn default() -> Self {
+        Negotiate::new()
+    }
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Negotiate<D> {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        res.headers_mut().append(header::VARY, HeaderValue::from_static("Accept"));
+
+        let offered: Vec<_> = self.formats.iter().map(|&(media_type, _)| media_type).collect();
+        let media_type = match req.negotiate(&offered) {
+            Some(media_type) => media_type,
+            None => return res.error(StatusCode::NOT_ACCEPTABLE, "No acceptable representation")
+        };
+        let (_, render) = self.formats.iter().find(|&&(mt, _)| mt == media_type).unwrap();
+
+        res.set(media_type);
+        match render {
+            Render::Handler(render) => render(req, res),
+            Render::Template(path, data) => match data(req) {
+                Ok(data) => res.render(&path[..], &data).await,
+                Err(e) => res.error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize template data: {}", e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use hyper::{Method, StatusCode};
+    use hyper::header::{self, HeaderValue};
+    use serde_json::json;
+    use crate::default_error_handler::DefaultErrorHandler;
+    use crate::middleware::MiddlewareStack;
+    use crate::mimes::MediaType;
+    use crate::router::{HttpRouter, Router};
+    use crate::test_util::{request, run_stack};
+    use super::{Negotiate, negotiate_accept};
+
+    fn accept(value: &'static str, offered: &[MediaType]) -> Option<MediaType> {
+        negotiate_accept(Some(&HeaderValue::from_static(value)).into_iter(), offered)
+    }
+
+    #[test]
+    fn negotiates_accept_header() {
+        use crate::mimes::MediaType::{Html, Json, Txt};
+
+        assert_eq!(accept("application/json", &[Html, Json]), Some(Json));
+        assert_eq!(accept("text/*;q=0.5, application/json;q=0.4", &[Json, Html]), Some(Html));
+        assert_eq!(accept("*/*", &[Json, Html]), Some(Json));
+        assert_eq!(accept("text/*, text/plain;q=0", &[Txt, Html]), Some(Html));
+        assert_eq!(accept("image/png", &[Json, Html]), None);
+        assert_eq!(accept("text/html;level=1;q=0.2, */*;q=0.1", &[Json, Html]), Some(Html));
+        assert_eq!(negotiate_accept(None.into_iter(), &[Txt, Html]), Some(Txt));
+    }
+
+    #[tokio::test]
+    async fn negotiate_middleware() {
+        let mut router = Router::new();
+        router.get("/users/:id", Negotiate::new()
+            .json(|request| json!({ "id": request.param("id") }))
+            .text(|request| format!("user {}", request.param("id").unwrap())));
+        let mut stack = MiddlewareStack::new();
+        stack.add_error_handler(DefaultErrorHandler);
+        stack.add_middleware(router);
+
+        let run = |uri, accept| {
+            let mut req = request(Method::GET, uri, Arc::new(()));
+            if let Some(accept) = accept {
+                req.origin.headers_mut().insert(header::ACCEPT, HeaderValue::from_static(accept));
+            }
+            run_stack(&stack, req)
+        };
+
+        assert_eq!(run("/users/42", None).await, (StatusCode::OK, r#"{"id":"42"}"#.to_string()));
+        assert_eq!(run("/users/42", Some("text/plain")).await, (StatusCode::OK, "user 42".to_string()));
+        assert_eq!(run("/users/42.txt", Some("application/json")).await.1, "user 42");
+        assert_eq!(run("/users/42.html", None).await.0, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(run("/users/42", Some("image/png")).await.0, StatusCode::NOT_ACCEPTABLE);
+    }
+}
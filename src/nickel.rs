@@ -2,8 +2,12 @@ use std::net::ToSocketAddrs;
 use std::time::Duration;
 use std::env;
 use std::error::Error as StdError;
-use crate::router::{Router, HttpRouter, Matcher};
-use crate::middleware::{MiddlewareStack, Middleware, ErrorHandler};
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::router::{Router, HttpRouter, Matcher, RouteInfo, RouteConflict, RouteTable, find_conflicts};
+use crate::middleware::{MiddlewareStack, Middleware, MiddlewareResult, ErrorHandler, Continue, Halt};
+use crate::request::Request;
+use crate::response::Response;
 use crate::server::Server;
 use crate::template_cache::ReloadPolicy;
 use hyper::{Method, StatusCode};
@@ -31,6 +35,7 @@ pub struct Options {
     output_on_listen: bool,
     thread_count: Option<usize>,
     reload_policy: ReloadPolicy,
+    route_table: Option<String>,
 }
 
 impl Options {
@@ -56,6 +61,16 @@ impl Options {
         self.reload_policy = reload_policy;
         self
     }
+
+    /// Serve the route table (see `Nickel::routes`) as JSON on GET requests
+    /// to the given path. Meant for debugging, it should usually not be
+    /// enabled in production.
+    ///
+    /// Defaults to `None`.
+    pub fn route_table(mut self, path: Option<&str>) -> Self {
+        self.route_table = path.map(|p| p.to_string());
+        self
+    }
 }
 
 impl Default for Options {
@@ -64,6 +79,7 @@ impl Default for Options {
             output_on_listen: true,
             thread_count: None,
             reload_policy: ReloadPolicy::Never,
+            route_table: None,
         }
     }
 }
@@ -71,8 +87,9 @@ impl Default for Options {
 /// Nickel is the application object. It's the surface that
 /// holds all public APIs.
 pub struct Nickel<D: Sync + Send + 'static = ()> {
-    middleware_stack: MiddlewareStack<D>,
-    data: D,
+    // Only shared once the app is running, see `stack_mut`.
+    middleware_stack: Arc<MiddlewareStack<D>>,
+    data: Arc<D>,
     keep_alive_timeout: Option<Duration>,
 
     /// Configuration options for the server.
@@ -112,9 +129,9 @@ impl<D: Sync + Send + 'static> Nickel<D> {
         middleware_stack.add_error_handler(DefaultErrorHandler);
 
         Nickel {
-            middleware_stack: middleware_stack,
+            middleware_stack: Arc::new(middleware_stack),
             options: options,
-            data: data,
+            data: Arc::new(data),
             // Default value from nginx
             keep_alive_timeout: Some(Duration::from_secs(75)),
         }
@@ -148,7 +165,7 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     /// # }
     /// ```
     pub fn utilize<T: Middleware<D>>(&mut self, handler: T){
-        self.middleware_stack.add_middleware(handler);
+        self.stack_mut().add_middleware(handler);
     }
 
     /// Registers an error handler which will be invoked among other error handler
@@ -186,7 +203,7 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     /// # }
     /// ```
     pub fn handle_error<T: ErrorHandler<D>>(&mut self, handler: T){
-        self.middleware_stack.add_error_handler(handler);
+        self.stack_mut().add_error_handler(handler);
     }
 
     /// Create a new middleware to serve as a router.
@@ -212,6 +229,36 @@ impl<D: Sync + Send + 'static> Nickel<D> {
         Router::new()
     }
 
+    /// Lists every route registered in the middleware stack, including those
+    /// of mounted routers, in the order they are matched.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, Mountable};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     let mut router = Nickel::router();
+    ///     router.get("/users/:id", middleware! { "a user" }).name("user");
+    ///     server.mount("/api/", router).unwrap();
+    ///
+    ///     let route = &server.routes()[0];
+    ///     assert_eq!(route.name.as_ref().map(|n| &n[..]), Some("user"));
+    ///     assert_eq!(route.mount_prefix.as_ref().map(|p| &p[..]), Some("/api/"));
+    /// }
+    /// ```
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.middleware_stack.routes()
+    }
+
+    /// Finds routes which can never be reached, because they duplicate or
+    /// are fully shadowed by a route registered before them. These are also
+    /// logged as warnings when the server starts listening.
+    pub fn route_conflicts(&self) -> Vec<RouteConflict> {
+        find_conflicts(&self.routes())
+    }
+
     /// Bind and listen for connections on the given host and port.
     ///
     /// # Examples
@@ -225,7 +272,16 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     /// # listening.detach();
     /// ```
     pub async fn listen<T: ToSocketAddrs>(mut self, addr: T) -> Result<(), Box<dyn StdError>> {
-        self.middleware_stack.add_middleware(middleware! {
+        let routes = self.routes();
+        for conflict in find_conflicts(&routes) {
+            warn!("{}", conflict);
+        }
+        if let Some(ref path) = self.options.route_table {
+            let route_table = RouteTable::new(&path[..], &routes);
+            self.stack_mut().prepend_middleware(route_table);
+        }
+
+        self.stack_mut().add_middleware(middleware! {
             (StatusCode::NOT_FOUND, "File Not Found")
         });
 
@@ -259,6 +315,12 @@ impl<D: Sync + Send + 'static> Nickel<D> {
         Ok(())
     }
 
+    // The stack can only be changed before the app runs, which consumes it
+    // either through `listen` or by being moved into another app.
+    fn stack_mut(&mut self) -> &mut MiddlewareStack<D> {
+        Arc::get_mut(&mut self.middleware_stack).expect("middleware stack of a running app")
+    }
+
     /// Set the timeout for the keep-alive loop
     ///
     /// # Performance
@@ -341,6 +403,54 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     // }
 }
 
+/// A `Nickel` app can be used as middleware of another app, usually mounted
+/// under a prefix. It keeps its own server data, middleware and error
+/// handlers, so it can be built on its own and combined with apps of a
+/// different data type.
+///
+/// Requests which no middleware of the app halts on fall through to the
+/// middleware after it. Templates are shared with the outer app, its
+/// `Options` are not used.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Mountable};
+///
+/// struct Billing { currency: &'static str }
+///
+/// fn main() {
+///     let mut billing = Nickel::with_data(Billing { currency: "EUR" });
+///     billing.get("/invoices", middleware! { |_, res| <Billing>
+///         format!("Invoices in {}", res.server_data().currency)
+///     });
+///
+///     let mut server = Nickel::new();
+///     server.mount("/billing/", billing).unwrap();
+/// }
+/// ```
+#[async_trait]
+impl<D, D2> Middleware<D> for Nickel<D2>
+where D: Send + Sync + 'static, D2: Send + Sync + 'static {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        let data = res.data();
+        let mut app_req = req.take_with_data(self.data.clone());
+        let stack = res.stack();
+        let app_res = res.with_data(self.data.clone(), Some(self.middleware_stack.clone()));
+        let action = self.middleware_stack.dispatch(&mut app_req, app_res).await;
+        req.restore_from(app_req);
+
+        Ok(match action {
+            Continue(res) => Continue(res.with_data(data, stack)),
+            Halt(res) => Halt(res.with_data(data, stack))
+        })
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.middleware_stack.routes()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Nickel;
@@ -2,13 +2,16 @@ use std::borrow::Cow;
 use hyper::StatusCode;
 use std::io;
 use std::error::Error;
+use serde_json::Value;
+use crate::problem::Problem;
 use crate::response::Response;
 
 /// NickelError is the basic error type for HTTP errors as well as user defined errors.
 /// One can pattern match against the `kind` property to handle the different cases.
 pub struct NickelError<D: Send + 'static + Sync = ()> {
     pub stream: Option<Response<D>>,
-    pub message: Cow<'static, str>
+    pub message: Cow<'static, str>,
+    problem: Problem
 }
 
 impl<D: Send + 'static + Sync> NickelError<D> {
@@ -40,6 +43,7 @@ impl<D: Send + 'static + Sync> NickelError<D> {
         NickelError {
             stream: Some(stream),
             message: message.into(),
+            problem: Problem::default(),
         }
     }
 
@@ -57,9 +61,59 @@ impl<D: Send + 'static + Sync> NickelError<D> {
         NickelError {
             stream: None,
             message: message.into(),
+            problem: Problem::default(),
         }
     }
 
+    /// The details for the client, sent by `ProblemErrorHandler`.
+    pub fn problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    /// Sets a URI identifying the type of problem, which clients can tell
+    /// errors apart by.
+    pub fn problem_type<T: Into<String>>(mut self, problem_type: T) -> Self {
+        self.problem.problem_type = Some(problem_type.into());
+        self
+    }
+
+    /// Sets a short summary of the type of problem.
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.problem.title = Some(title.into());
+        self
+    }
+
+    /// Sets an explanation of this occurrence of the problem for the client.
+    pub fn detail<T: Into<String>>(mut self, detail: T) -> Self {
+        self.problem.detail = Some(detail.into());
+        self
+    }
+
+    /// Sets a URI identifying this occurrence of the problem.
+    pub fn instance<T: Into<String>>(mut self, instance: T) -> Self {
+        self.problem.instance = Some(instance.into());
+        self
+    }
+
+    /// Adds a member of its own to the problem. Names of the standard
+    /// members are ignored, with a warning when the problem is sent.
+    pub fn extension<K: Into<String>, V: Into<Value>>(mut self, name: K, value: V) -> Self {
+        self.problem.extensions.insert(name.into(), value.into());
+        self
+    }
+
+    /// Adds a failed validation of the request parameter `name` to the
+    /// `invalid-params` member of the problem.
+    pub fn invalid_param<N: Into<String>, R: Into<String>>(mut self, name: N, reason: R) -> Self {
+        let param = serde_json::json!({ "name": name.into(), "reason": reason.into() });
+        let params = self.problem.extensions.entry("invalid-params").or_insert_with(|| Value::Array(vec![]));
+        match params {
+            Value::Array(params) => params.push(param),
+            other => *other = Value::Array(vec![param])
+        }
+        self
+    }
+
     pub fn end(self) -> Option<io::Result<()>> {
         self.stream.map(|s| s.end())
     }
@@ -0,0 +1,264 @@
+//! Normalizing request paths before they are routed.
+use async_trait::async_trait;
+use hyper::StatusCode;
+use crate::extensions::Redirect;
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mount::{mount_state, with_path};
+use crate::request::Request;
+use crate::response::Response;
+
+/// What `NormalizePath` does with a trailing slash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailingSlash {
+    /// Keeps the path as it is, so `/users` and `/users/` stay different.
+    Keep,
+    /// Adds a trailing slash, `/users` becomes `/users/`.
+    Always,
+    /// Removes a trailing slash, `/users/` becomes `/users`.
+    Never,
+}
+
+/// Brings request paths into a canonical form, so the middleware after it
+/// doesn't have to deal with variations of the same path.
+///
+/// Duplicate slashes are collapsed and the dot segments `.` and `..` are
+/// resolved, also when they are percent-encoded, so `//a/./b/../c` becomes
+/// `/a/c`. The trailing slash is handled according to a `TrailingSlash`
+/// policy. A changed path is rewritten internally, or the client is
+/// redirected to it when a redirect status is given. Either way the path as
+/// requested stays available through `Request::original_uri`.
+///
+/// Routes can also be made to match the path without case, which doesn't
+/// change the path nor the params taken from it.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, NormalizePath, TrailingSlash};
+/// use nickel::status::StatusCode;
+///
+/// let mut server = Nickel::new();
+/// server.utilize(NormalizePath::new()
+///     .trailing_slash(TrailingSlash::Never)
+///     .redirect(StatusCode::PERMANENT_REDIRECT)
+///     .ignore_case());
+/// ```
+pub struct NormalizePath {
+    trailing_slash: TrailingSlash,
+    redirect: Option<StatusCode>,
+    ignore_case: bool,
+}
+
+impl NormalizePath {
+    /// Collapses slashes and resolves dot segments, keeping trailing slashes
+    /// and rewriting paths internally.
+    pub fn new() -> NormalizePath {
+        NormalizePath {
+            trailing_slash: TrailingSlash::Keep,
+            redirect: None,
+            ignore_case: false
+        }
+    }
+
+    /// Sets the policy for trailing slashes. The root path `/` is never
+    /// changed.
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Redirects clients to the normalized path with `status` instead of
+    /// rewriting it, so they and caches learn the canonical path.
+    ///
+    /// # Panics
+    /// Panics if `status` isn't a redirection.
+    pub fn redirect(mut self, status: StatusCode) -> Self {
+        assert!(status.is_redirection(), "NormalizePath needs a 3xx status to redirect, not {}", status);
+        self.redirect = Some(status);
+        self
+    }
+
+    /// Makes routes match the path without case. Inside a mount or a scope,
+    /// this only applies until the request leaves it.
+    pub fn ignore_case(mut self) -> Self {
+        self.ignore_case = true;
+        self
+    }
+
+    fn normalize(&self, path: &str) -> String {
+        let mut segments: Vec<&str> = Vec::new();
+        // whether the last segment was a dot segment, which stands for a
+        // directory like a trailing slash does
+        let mut ends_with_dot = false;
+
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            ends_with_dot = true;
+            if is_dot(segment, 2) {
+                segments.pop();
+            } else if !is_dot(segment, 1) {
+                segments.push(segment);
+                ends_with_dot = false;
+            }
+        }
+
+        let trailing = match self.trailing_slash {
+            TrailingSlash::Keep => ends_with_dot || path.ends_with('/'),
+            TrailingSlash::Always => true,
+            TrailingSlash::Never => false
+        };
+
+        let mut normalized = String::with_capacity(path.len());
+        for segment in &segments {
+            normalized.push('/');
+            normalized.push_str(segment);
+        }
+        if trailing || segments.is_empty() {
+            normalized.push('/');
+        }
+        normalized
+    }
+}
+
+impl Default for NormalizePath {
+    fn default() -> Self {
+        NormalizePath::new()
+    }
+}
+
+// Whether `segment` is made of `dots` dots, which may be percent-encoded.
+fn is_dot(segment: &str, dots: usize) -> bool {
+    let mut rest = segment;
+    for _ in 0..dots {
+        rest = match rest.strip_prefix('.') {
+            Some(rest) => rest,
+            None if rest.len() >= 3 && rest[..3].eq_ignore_ascii_case("%2e") => &rest[3..],
+            None => return false
+        };
+    }
+    rest.is_empty()
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for NormalizePath {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        if self.ignore_case {
+            req.ignore_case = true;
+        }
+
+        let path = self.normalize(req.path_without_query());
+        if path == req.path_without_query() {
+            return res.next_middleware();
+        }
+
+        let query = req.origin.uri().query().map(str::to_string);
+        if let Some(status) = self.redirect {
+            let mut location = format!("{}{}", req.base_path(), path);
+            if let Some(query) = query {
+                location = format!("{}?{}", location, query);
+            }
+            return res.redirect_with(location, status);
+        }
+
+        let uri = match with_path(req.origin.uri(), &path, query.as_deref()) {
+            Some(uri) => uri,
+            None => return res.error(StatusCode::BAD_REQUEST, "Invalid path")
+        };
+        req.mounted = Some(mount_state(req));
+        *req.origin.uri_mut() = uri;
+        res.next_middleware()
+    }
+}
+
+#[test]
+fn normalizes_paths() {
+    let keep = NormalizePath::new();
+    let never = NormalizePath::new().trailing_slash(TrailingSlash::Never);
+    let always = NormalizePath::new().trailing_slash(TrailingSlash::Always);
+
+    let cases = &[
+        // path, kept, never, always
+        ("/", "/", "/", "/"),
+        ("//", "/", "/", "/"),
+        ("/users", "/users", "/users", "/users/"),
+        ("/users/", "/users/", "/users", "/users/"),
+        ("//users///1", "/users/1", "/users/1", "/users/1/"),
+        ("/a/./b/../c", "/a/c", "/a/c", "/a/c/"),
+        ("/a/b/..", "/a/", "/a", "/a/"),
+        ("/a/b/.", "/a/b/", "/a/b", "/a/b/"),
+        ("/../../a", "/a", "/a", "/a/"),
+        ("/a/%2e%2E/b/%2E", "/b/", "/b", "/b/"),
+        ("/a/.../b", "/a/.../b", "/a/.../b", "/a/.../b/"),
+        ("/a/.b/%2e.c", "/a/.b/%2e.c", "/a/.b/%2e.c", "/a/.b/%2e.c/"),
+    ];
+
+    for &(path, kept, without, with) in cases {
+        assert_eq!(keep.normalize(path), kept, "keeping the slash of {}", path);
+        assert_eq!(never.normalize(path), without, "removing the slash of {}", path);
+        assert_eq!(always.normalize(path), with, "adding a slash to {}", path);
+    }
+}
+
+#[tokio::test]
+async fn rewrites_or_redirects_to_normalized_paths() {
+    use std::sync::Arc;
+    use hyper::{header, Method};
+    use crate::{HttpRouter, Router};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, response, run_stack};
+
+    let mut router = Router::new();
+    router.get("/users/:name", middleware! { |request|
+        format!("{} at {} from {}", request.param("name").unwrap(), request.origin.uri(), request.original_uri())
+    });
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(NormalizePath::new().trailing_slash(TrailingSlash::Never).ignore_case());
+    stack.add_middleware(router);
+    let run = |uri| run_stack(&stack, request(Method::GET, uri, Arc::new(())));
+
+    assert_eq!(run("//users/./Alice/?x=1").await, (StatusCode::OK, "Alice at /users/Alice?x=1 from //users/./Alice/?x=1".to_string()));
+    assert_eq!(run("/Users/bob").await, (StatusCode::OK, "bob at /Users/bob from /Users/bob".to_string()));
+
+    let mut strict = Router::new();
+    strict.get("/users/:name", middleware! { "found" });
+    let mut redirecting = MiddlewareStack::new();
+    redirecting.add_middleware(NormalizePath::new().redirect(StatusCode::MOVED_PERMANENTLY));
+    redirecting.add_middleware(strict);
+
+    let res = redirecting.invoke(request(Method::GET, "/a/../users//bob?x=1", Arc::new(())), response(Arc::new(()))).await;
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers()[header::LOCATION], "/users/bob?x=1");
+    assert_eq!(run_stack(&redirecting, request(Method::GET, "/users/bob", Arc::new(()))).await.1, "found");
+    assert_eq!(run_stack(&redirecting, request(Method::GET, "/Users/bob", Arc::new(()))).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn ignores_case_within_mounts_and_scopes_only() {
+    use std::sync::Arc;
+    use hyper::Method;
+    use crate::{HttpRouter, MiddlewareResult, Mount, Request, Response, Router};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_stack};
+
+    fn pass(_: &mut Request<()>, res: Response<()>) -> MiddlewareResult<()> {
+        res.next_middleware()
+    }
+
+    let mut scoped = Router::new();
+    scoped.scope("/scoped", |scope| {
+        scope.utilize(NormalizePath::new().ignore_case());
+        scope.get("/a", pass);
+    });
+    let mut router = Router::new();
+    router.get("/API/users", middleware! { "api users" });
+    router.get("/Scoped/A", middleware! { "scoped a" });
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(Mount::new("/api/", NormalizePath::new().ignore_case()).unwrap());
+    stack.add_middleware(scoped);
+    stack.add_middleware(router);
+    let run = |uri| run_stack(&stack, request(Method::GET, uri, Arc::new(())));
+
+    assert_eq!(run("/API/users").await.1, "api users");
+    assert_eq!(run("/api/users").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(run("/scoped/a").await.1, "");
+}
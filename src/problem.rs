@@ -0,0 +1,145 @@
+//! Structured error responses following RFC 7807, "Problem Details for HTTP
+//! APIs".
+use hyper::StatusCode;
+use hyper::header;
+use serde_json::{Map, Value};
+use crate::middleware::{Action, ErrorHandler, Halt};
+use crate::mimes::MediaType;
+use crate::nickel_error::NickelError;
+use crate::request::Request;
+
+/// The details of an error which `ProblemErrorHandler` sends, set with the
+/// builder methods of `NickelError`.
+///
+/// Members which aren't set are left out, except for `type`, which is
+/// `about:blank`, and `title`, which is the reason phrase of the status
+/// then. The message of the error isn't sent, as it is often meant for the
+/// logs rather than the client, see `NickelError::detail` for that.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Problem {
+    pub problem_type: Option<String>,
+    pub title: Option<String>,
+    pub detail: Option<String>,
+    pub instance: Option<String>,
+    pub extensions: Map<String, Value>,
+}
+
+// The members defined by the RFC, which extensions can't replace.
+const MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+impl Problem {
+    /// The problem as a JSON object, for a response with `status`.
+    pub fn to_json(&self, status: StatusCode) -> Value {
+        let mut json = Map::new();
+        json.insert("type".to_string(), self.problem_type.as_deref().unwrap_or("about:blank").into());
+        let title = self.title.as_deref().or_else(|| status.canonical_reason()).unwrap_or("Error");
+        json.insert("title".to_string(), title.into());
+        json.insert("status".to_string(), status.as_u16().into());
+        if let Some(ref detail) = self.detail {
+            json.insert("detail".to_string(), detail.clone().into());
+        }
+        if let Some(ref instance) = self.instance {
+            json.insert("instance".to_string(), instance.clone().into());
+        }
+        for (name, value) in &self.extensions {
+            if MEMBERS.contains(&&name[..]) {
+                warn!("Dropped the extension '{}' of a problem, it is a standard member", name);
+            } else {
+                json.insert(name.clone(), value.clone());
+            }
+        }
+        Value::Object(json)
+    }
+}
+
+/// An error handler sending errors as `application/problem+json`.
+///
+/// It is opt-in: register it with `handle_error` on the server, or on a
+/// router or mount to use it for an API only, and it takes over from
+/// `DefaultErrorHandler` there.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult, NickelError, ProblemErrorHandler};
+/// use nickel::status::StatusCode;
+///
+/// fn create_user(req: &mut Request, res: Response) -> MiddlewareResult {
+///     let age = req.param("age").unwrap_or("");
+///     if age.parse::<u8>().is_err() {
+///         return Err(NickelError::new(res, format!("invalid age {:?}", age), StatusCode::UNPROCESSABLE_ENTITY)
+///             .problem_type("https://example.com/probs/validation")
+///             .title("Your request parameters didn't validate")
+///             .instance(req.path_without_query().to_string())
+///             .invalid_param("age", "must be a number from 0 to 255"));
+///     }
+///     res.send("created")
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.post("/users/:age", create_user);
+/// server.handle_error(ProblemErrorHandler);
+/// ```
+#[derive(Clone, Copy)]
+pub struct ProblemErrorHandler;
+
+impl<D: Send + 'static + Sync> ErrorHandler<D> for ProblemErrorHandler {
+    fn handle_error(&self, err: &mut NickelError<D>, _req: &mut Request<D>) -> Action {
+        let problem = err.stream.as_ref().map(|res| err.problem().to_json(res.status()));
+        if let (Some(problem), Some(res)) = (problem, err.stream.as_mut()) {
+            res.set_header(header::CONTENT_TYPE, MediaType::ProblemJson);
+            res.set_body(problem.to_string());
+        } else {
+            error!("Error: {}", err.message);
+        }
+
+        Halt(())
+    }
+}
+
+#[tokio::test]
+async fn sends_problems() {
+    use hyper::Method;
+    use crate::{HttpRouter, MiddlewareResult, Response, Router};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_stack};
+
+    fn invalid(_: &mut Request<()>, res: Response<()>) -> MiddlewareResult<()> {
+        Err(NickelError::new(res, "internal", StatusCode::UNPROCESSABLE_ENTITY)
+            .problem_type("https://example.com/probs/validation")
+            .title("Invalid")
+            .detail("Two fields are invalid")
+            .instance("/users/1")
+            .extension("balance", 30)
+            .extension("status", "ignored")
+            .invalid_param("age", "too young")
+            .invalid_param("name", "missing"))
+    }
+
+    let mut router = Router::new();
+    router.get("/invalid", invalid);
+    router.get("/plain", |_: &mut Request<()>, res: Response<()>| res.error(StatusCode::NOT_FOUND, "no such thing"));
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(ProblemErrorHandler);
+    stack.add_middleware(router);
+
+    let (status, body) = run_stack(&stack, request(Method::GET, "/invalid", std::sync::Arc::new(()))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json, serde_json::json!({
+        "type": "https://example.com/probs/validation",
+        "title": "Invalid",
+        "status": 422,
+        "detail": "Two fields are invalid",
+        "instance": "/users/1",
+        "balance": 30,
+        "invalid-params": [
+            { "name": "age", "reason": "too young" },
+            { "name": "name", "reason": "missing" }
+        ]
+    }));
+
+    let (status, body) = run_stack(&stack, request(Method::GET, "/plain", std::sync::Arc::new(()))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(),
+               serde_json::json!({ "type": "about:blank", "title": "Not Found", "status": 404 }));
+}
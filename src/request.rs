@@ -4,7 +4,7 @@ use crate::router::RouteResult;
 //use plugin::{Extensible, Pluggable};
 
 use typemap::{ShareMap, TypeMap};
-use hyper::{Body, Request as HyperRequest, StatusCode};
+use hyper::{Body, Request as HyperRequest, StatusCode, Uri};
 use hyper::body::{self, Bytes};
 use hyper::header;
 use serde::Deserialize;
@@ -29,6 +29,25 @@ pub struct Request<D = ()> {
     remote_addr: Option<SocketAddr>,
 
     raw_body_cache: Option<Bytes>,
+
+    pub(crate) mounted: Option<MountState>,
+
+    // How deep in forwards and sub-requests this request is.
+    pub(crate) forwards: usize,
+
+    // Whether routes match the path without case, see `NormalizePath`.
+    pub(crate) ignore_case: bool,
+}
+
+/// Where a request has been mounted, see `Mount`.
+#[derive(Clone)]
+pub(crate) struct MountState {
+    /// The uri before any mount point was stripped.
+    pub original_uri: Uri,
+    /// The path stripped by all enclosing mounts, without trailing slash.
+    pub base_path: String,
+    /// Params captured from the mount points.
+    pub params: Vec<(String, String)>,
 }
 
 impl<D> Request<D> {
@@ -42,12 +61,50 @@ impl<D> Request<D> {
             map: TypeMap::custom(),
             data: data,
             remote_addr: remote_addr,
-            raw_body_cache: None
+            raw_body_cache: None,
+            mounted: None,
+            forwards: 0,
+            ignore_case: false
         }
     }
 
+    /// Looks up a param captured by the matched route, or by the mount
+    /// point(s) the request was dispatched through.
     pub fn param(&self, key: &str) -> Option<&str> {
-        self.route_result.as_ref().unwrap().param(key)
+        self.route_result.as_ref().and_then(|r| r.param(key)).or_else(|| {
+            let params = &self.mounted.as_ref()?.params;
+            params.iter().rev().find(|(k, _)| k == key).map(|(_, v)| &v[..])
+        })
+    }
+
+    /// The uri as it was requested by the client. Unlike `origin.uri()` this
+    /// isn't affected by any `Mount` stripping its mount point.
+    pub fn original_uri(&self) -> &Uri {
+        self.mounted.as_ref().map_or(self.origin.uri(), |m| &m.original_uri)
+    }
+
+    /// The part of the original path which was stripped by the enclosing
+    /// `Mount`(s), without trailing slash. This is empty outside of a mount.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, Mountable};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     let mut router = Nickel::router();
+    ///
+    ///     // builds `/teams/:team/members/42` wherever the router is mounted
+    ///     router.get("/", middleware! { |request|
+    ///         format!("<a href=\"{}/members/42\">Member</a>", request.base_path())
+    ///     });
+    ///
+    ///     server.mount("/teams/:team/", router).unwrap();
+    /// }
+    /// ```
+    pub fn base_path(&self) -> &str {
+        self.mounted.as_ref().map_or("", |m| &m.base_path[..])
     }
 
     pub fn path_without_query(&self) -> &str {
@@ -73,6 +130,32 @@ impl<D> Request<D> {
         &mut self.map
     }
 
+    // Moves the request into one for an app with different server data, see
+    // `Nickel` as `Middleware`. The route result stays behind, it belongs to
+    // the routing of this app.
+    pub(crate) fn take_with_data<D2>(&mut self, data: Arc<D2>) -> Request<D2> {
+        Request {
+            origin: mem::replace(&mut self.origin, HyperRequest::new(Body::empty())),
+            body_taken: self.body_taken,
+            route_result: None,
+            map: mem::replace(&mut self.map, TypeMap::custom()),
+            data,
+            remote_addr: self.remote_addr,
+            raw_body_cache: self.raw_body_cache.take(),
+            mounted: self.mounted.clone(),
+            forwards: self.forwards,
+            ignore_case: self.ignore_case
+        }
+    }
+
+    // Takes back what `take_with_data` moved out.
+    pub(crate) fn restore_from<D2>(&mut self, other: Request<D2>) {
+        self.origin = other.origin;
+        self.body_taken = other.body_taken;
+        self.map = other.map;
+        self.raw_body_cache = other.raw_body_cache;
+    }
+
     /// Take the body from the hyper request. Once taken the body is not longer
     /// available. This method will return `None` in that case.
     ///
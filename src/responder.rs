@@ -9,10 +9,12 @@
 //! in any request.
 //!
 //! Please see the examples for usage.
-use crate::{Response, NickelError, MiddlewareResult, Halt};
-use hyper::StatusCode;
-use hyper::header;
-use serde_json;
+use std::convert::TryInto;
+use std::fmt;
+use crate::{Response, NickelError, MiddlewareResult, Halt, Json};
+use hyper::{Body, Response as HyperResponse, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
 use crate::mimes::MediaType;
 
 /// This trait provides convenience for translating a number
@@ -31,10 +33,8 @@ impl<D: Send + 'static + Sync> Responder<D> for () {
 }
 
 impl<D: Send + 'static + Sync> Responder<D> for serde_json::Value {
-    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
-        maybe_set_type(&mut res, MediaType::Json);
-        res.send(serde_json::to_string(&self)
-                      .map_err(|e| format!("Failed to parse JSON: {}", e)))
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        res.send(Json::new(self))
     }
 }
 
@@ -47,27 +47,76 @@ for<> NickelError<D>: From<(Response<D>, E)> {
     }
 }
 
-impl <D: Send + 'static + Sync> Responder<D> for &[u8] {
-    #[allow(unused_mut)]
+impl<D: Send + 'static + Sync> Responder<D> for &[u8] {
     #[inline]
-    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
-        // this may be inefficient, copies int a Vec
-        self.to_vec().respond(res)
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        // the body has to own its bytes, this is the one copy needed
+        Bytes::copy_from_slice(self).respond(res)
     }
 }
 
-impl <D: Send + 'static + Sync> Responder<D> for Vec<u8> {
-    #[allow(unused_mut)]
+impl<D: Send + 'static + Sync> Responder<D> for Vec<u8> {
     #[inline]
-    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
-        maybe_set_type(&mut res, MediaType::Bin);
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        Ok(Halt(with_body(res, self, MediaType::Bin)))
+    }
+}
 
+impl<D: Send + 'static + Sync> Responder<D> for Bytes {
+    #[inline]
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        Ok(Halt(with_body(res, self, MediaType::Bin)))
+    }
+}
+
+impl<D: Send + 'static + Sync> Responder<D> for Body {
+    #[inline]
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        Ok(Halt(with_body(res, self, MediaType::Bin)))
+    }
+}
+
+/// Sends a response built with hyper as it is: its status, headers and body
+/// replace those of `res`, the headers it doesn't have are kept.
+impl<D: Send + 'static + Sync> Responder<D> for HyperResponse<Body> {
+    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
+        let (parts, body) = self.into_parts();
+        res.set(parts.status);
+        res.headers_mut().extend(parts.headers);
         res.start();
-        res.set_body(self);
+        res.set_body(body);
         Ok(Halt(res))
     }
 }
 
+/// `None` is a `404 Not Found`, which makes handlers looking things up short.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use std::collections::HashMap;
+/// use nickel::{Nickel, HttpRouter};
+///
+/// fn main() {
+///     let mut users = HashMap::new();
+///     users.insert("1".to_string(), "Alice".to_string());
+///
+///     let mut server = Nickel::new();
+///     server.get("/users/:id", middleware! { |req|
+///         users.get(req.param("id").unwrap()).cloned()
+///     });
+/// }
+/// ```
+impl<T, D: Send + 'static + Sync> Responder<D> for Option<T>
+where T: Responder<D> {
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        match self {
+            Some(data) => res.send(data),
+            None => res.error(StatusCode::NOT_FOUND, "Not Found")
+        }
+    }
+}
+
 macro_rules! dual_impl {
     ($view:ty, $alloc:ty, |$s:ident, $res:ident| $b:block) => (
         impl<D: Send + 'static + Sync> Responder<D> for $view {
@@ -84,12 +133,19 @@ macro_rules! dual_impl {
     )
 }
 
-dual_impl!(&str,
-           String,
-            |self, res| {
-                maybe_set_type(&mut res, MediaType::Html);
-                res.send(self.as_bytes())
-            });
+impl<D: Send + 'static + Sync> Responder<D> for &str {
+    #[inline]
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        Ok(Halt(with_body(res, Bytes::copy_from_slice(self.as_bytes()), MediaType::Html)))
+    }
+}
+
+impl<D: Send + 'static + Sync> Responder<D> for String {
+    #[inline]
+    fn respond(self, res: Response<D>) -> MiddlewareResult<D> {
+        Ok(Halt(with_body(res, self, MediaType::Html)))
+    }
+}
 
 dual_impl!((StatusCode, &'static str),
            (StatusCode, String),
@@ -126,26 +182,166 @@ dual_impl!((u16, &'static str),
                res.send((status, message))
             });
 
-// FIXME: Hyper uses traits for headers, so this needs to be a Vec of
-// trait objects. But, a trait object is unable to have Foo + Bar as a bound.
-//
-// A better/faster solution would be to impl this for tuples,
-// where each tuple element implements the Header trait, which would give a
-// static dispatch.
-// dual_impl!((StatusCode, &'a str, Vec<Box<ResponseHeader>>),
-//            (StatusCode, String, Vec<Box<ResponseHeader>>)
-//            |self, res| {
-//                 let (status, data, headers) = self;
-
-//                 res.origin.status = status;
-//                 for header in headers.into_iter() {
-//                     res.origin.headers_mut().set(header);
-//                 }
-//                 maybe_set_type(&mut res, MediaType::Html);
-//                 res.send(data);
-//                 Ok(Halt)
-//             })
+/// Headers to send along with a body, see the `Responder` impls for
+/// `(StatusCode, H, B)` and `(H, B)`.
+///
+/// Implemented for a `HeaderMap` and for lists of name and value pairs, which
+/// may be strings. Names or values which aren't valid in a header get
+/// `500 Internal Server Error`.
+pub trait ResponseHeaders {
+    /// The headers as a `HeaderMap`, or why they aren't valid.
+    fn into_header_map(self) -> Result<HeaderMap, String>;
+}
+
+impl ResponseHeaders for HeaderMap {
+    fn into_header_map(self) -> Result<HeaderMap, String> {
+        Ok(self)
+    }
+}
+
+fn header_map<I, K, V>(pairs: I) -> Result<HeaderMap, String>
+where I: IntoIterator<Item = (K, V)>,
+      K: TryInto<HeaderName>,
+      K::Error: fmt::Display,
+      V: TryInto<HeaderValue>,
+      V::Error: fmt::Display {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        let name = name.try_into().map_err(|e| format!("Invalid header name: {}", e))?;
+        let value = value.try_into().map_err(|e| format!("Invalid value for header '{}': {}", name, e))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+impl<K, V> ResponseHeaders for Vec<(K, V)>
+where K: TryInto<HeaderName>,
+      K::Error: fmt::Display,
+      V: TryInto<HeaderValue>,
+      V::Error: fmt::Display {
+    fn into_header_map(self) -> Result<HeaderMap, String> {
+        header_map(self)
+    }
+}
+
+impl<K, V, const N: usize> ResponseHeaders for [(K, V); N]
+where K: TryInto<HeaderName>,
+      K::Error: fmt::Display,
+      V: TryInto<HeaderValue>,
+      V::Error: fmt::Display {
+    fn into_header_map(self) -> Result<HeaderMap, String> {
+        header_map(self)
+    }
+}
+
+/// Sends `body` with a status and headers, which replace those of the same
+/// name set before.
+///
+/// Unlike `(StatusCode, &str)`, error statuses don't go to the error
+/// handler, the body is sent as it is.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Json};
+/// use nickel::status::StatusCode;
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.post("/users", middleware! {
+///         (StatusCode::CREATED, [("location", "/users/42")], Json::new(42))
+///     });
+///     server.get("/report", middleware! {
+///         ([("cache-control", "no-store")], "<h1>Report</h1>")
+///     });
+/// }
+/// ```
+impl<H, B, D: Send + 'static + Sync> Responder<D> for (StatusCode, H, B)
+where H: ResponseHeaders,
+      B: Responder<D> {
+    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
+        let (status, headers, body) = self;
+        res.set(status);
+        (headers, body).respond(res)
+    }
+}
+
+/// Sends `body` with headers, see `(StatusCode, H, B)`.
+impl<H, B, D: Send + 'static + Sync> Responder<D> for (H, B)
+where H: ResponseHeaders,
+      B: Responder<D> {
+    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
+        let (headers, body) = self;
+        match headers.into_header_map() {
+            Ok(headers) => res.headers_mut().extend(headers),
+            Err(e) => return res.error(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+        res.send(body)
+    }
+}
+
+// `res` with `body`, as `media_type` unless a content type is set already.
+fn with_body<D, B>(mut res: Response<D>, body: B, media_type: MediaType) -> Response<D>
+where D: Send + 'static + Sync,
+      B: Into<Body> {
+    maybe_set_type(&mut res, media_type);
+    res.start();
+    res.set_body(body);
+    res
+}
 
 fn maybe_set_type<D: Send + 'static + Sync>(res: &mut Response<D>, media_type: MediaType) {
     res.set_header_fallback(&header::CONTENT_TYPE, &media_type.into());
 }
+
+#[tokio::test]
+async fn responds_with_tuples_options_and_bodies() {
+    use std::sync::Arc;
+    use hyper::body;
+    use crate::extensions::Redirection;
+    use crate::test_util::response;
+
+    async fn send<R: Responder<()>>(responder: R) -> (StatusCode, HeaderMap, String) {
+        let res = match response(Arc::new(())).send(responder) {
+            Ok(Halt(res)) => res,
+            Err(err) => err.stream.unwrap(),
+            _ => panic!("expected the response to halt")
+        };
+        let (parts, body) = res.origin.into_parts();
+        (parts.status, parts.headers, String::from_utf8(body::to_bytes(body).await.unwrap().to_vec()).unwrap())
+    }
+
+    let (status, headers, body) = send((StatusCode::CREATED, [("location", "/users/42")], "created")).await;
+    assert_eq!((status, &body[..]), (StatusCode::CREATED, "created"));
+    assert_eq!(headers[header::LOCATION], "/users/42");
+    assert_eq!(headers[header::CONTENT_TYPE], "text/html");
+
+    let pairs = vec![(header::CONTENT_TYPE, HeaderValue::from_static("text/csv")),
+                     (header::VARY, HeaderValue::from_static("a")),
+                     (header::VARY, HeaderValue::from_static("b"))];
+    let (_, headers, body) = send((pairs, b"a,b".to_vec())).await;
+    assert_eq!(body, "a,b");
+    assert_eq!(headers[header::CONTENT_TYPE], "text/csv");
+    assert_eq!(headers.get_all(header::VARY).iter().count(), 2);
+
+    let mut map = HeaderMap::new();
+    map.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    assert_eq!(send((StatusCode::IM_A_TEAPOT, map, Bytes::from_static(b"tea"))).await.0, StatusCode::IM_A_TEAPOT);
+    assert_eq!(send(([("bad name", "x")], "")).await.0, StatusCode::INTERNAL_SERVER_ERROR);
+
+    assert_eq!(send(Some("found")).await.2, "found");
+    assert_eq!(send(None::<String>).await.0, StatusCode::NOT_FOUND);
+
+    let (_, headers, body) = send(Body::from("raw")).await;
+    assert_eq!(body, "raw");
+    assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+    let built = HyperResponse::builder().status(StatusCode::ACCEPTED).header("x-built", "yes").body(Body::from("hyper")).unwrap();
+    let (status, headers, body) = send(built).await;
+    assert_eq!((status, &body[..]), (StatusCode::ACCEPTED, "hyper"));
+    assert_eq!(headers["x-built"], "yes");
+
+    let (status, headers, _) = send(Redirection::permanently_to("/new")).await;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[header::LOCATION], "/new");
+    assert_eq!(send(Redirection::with("/new", StatusCode::OK)).await.0, StatusCode::INTERNAL_SERVER_ERROR);
+}
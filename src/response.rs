@@ -1,12 +1,19 @@
 use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
 use chrono::prelude::Utc;
 use std::path::Path;
 use serde::Serialize;
-use hyper::{Body, Response as HyperResponse, StatusCode};
+use hyper::{Body, Request as HyperRequest, Response as HyperResponse, StatusCode, Uri, Version};
+use hyper::body::{self, Bytes};
+use hyper::upgrade::{self, Upgraded};
 use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
 use crate::mimes::MediaType;
 use std::io;
-use crate::{NickelError, Halt, MiddlewareResult, Responder, Action};
+use std::mem;
+use crate::{NickelError, Halt, MiddlewareResult, Responder, Action, Request};
+use crate::middleware::MiddlewareStack;
+use crate::response_writer::ResponseWriter;
 use crate::template_cache::TemplateCache;
 use modifier::Modifier;
 use std::sync::Arc;
@@ -14,6 +21,10 @@ use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 use typemap::{ShareMap, TypeMap};
 
+// How often a request may be forwarded, or spawn nested sub-requests, before
+// it is considered a loop.
+const MAX_FORWARDS: usize = 10;
+
 ///A container for the response
 pub struct Response<D: Send + 'static + Sync = ()> {
     ///the original `hyper::server::Response`
@@ -21,6 +32,9 @@ pub struct Response<D: Send + 'static + Sync = ()> {
     templates: Arc<TemplateCache>,
     data: Arc<D>,
     map: ShareMap,
+    // The stack the response is dispatched through, for forwards and
+    // sub-requests.
+    stack: Option<Arc<MiddlewareStack<D>>>,
     // This should be FnBox, but that's currently unstable
     //on_send: Vec<Box<dyn FnMut(&mut Response<'a, D>)>>
 }
@@ -35,6 +49,7 @@ impl<D: Send + 'static + Sync> Response<D> {
             templates: templates,
             data: data,
             map: TypeMap::custom(),
+            stack: None,
             //on_send: vec![]
         }
     }
@@ -115,15 +130,18 @@ impl<D: Send + 'static + Sync> Response<D> {
 
     /// Writes a file to the output.
     ///
+    /// The whole file is sent, whatever the request. See `send_file_for` to
+    /// honour conditional and range requests.
+    ///
     /// # Examples
     /// ```{rust}
     /// use nickel::{Request, Response, MiddlewareResult};
     /// use std::path::Path;
     ///
     /// # #[allow(dead_code)]
-    /// fn handler<D>(_: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+    /// async fn handler<D: Send + Sync + 'static>(_: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
     ///     let favicon = Path::new("/assets/favicon.ico");
-    ///     res.send_file(favicon)
+    ///     res.send_file(favicon).await
     /// }
     /// ```
     pub async fn send_file<P:AsRef<Path>>(mut self, path: P) -> MiddlewareResult<D> {
@@ -149,6 +167,97 @@ impl<D: Send + 'static + Sync> Response<D> {
         }
     }
 
+    /// Streams the body to the client as `write` produces it, which is useful
+    /// for large exports or reports which shouldn't be held in memory at
+    /// once.
+    ///
+    /// `write` runs after the response has been handed to the server, so the
+    /// status and headers have to be set before. Writes wait for the client
+    /// to catch up, see `ResponseWriter`. If `write` fails, the stream is
+    /// bailed out of with its error, see `ResponseWriter::bail`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Request, Response, MiddlewareResult, MediaType};
+    ///
+    /// # #[allow(dead_code)]
+    /// fn export(_: &mut Request, mut res: Response) -> MiddlewareResult {
+    ///     res.set(MediaType::Csv);
+    ///     res.stream(|mut writer| async move {
+    ///         writer.write("id,name\n").await?;
+    ///         for id in 0..100_000 {
+    ///             writer.write(format!("{},user {}\n", id, id)).await?;
+    ///         }
+    ///         Ok::<_, std::io::Error>(())
+    ///     })
+    /// }
+    /// ```
+    // like every handler, the error carries the response back
+    #[allow(clippy::result_large_err)]
+    pub fn stream<F, Fut, E>(mut self, write: F) -> MiddlewareResult<D>
+    where F: FnOnce(ResponseWriter) -> Fut,
+          Fut: Future<Output = Result<(), E>> + Send + 'static,
+          E: fmt::Display {
+        self.origin.headers_mut().remove(header::CONTENT_LENGTH);
+        self.start();
+
+        let (writer, body) = ResponseWriter::new();
+        let bail = writer.another();
+        let writing = write(writer);
+        tokio::spawn(async move {
+            let message = match writing.await {
+                Ok(()) => return,
+                Err(e) => e.to_string()
+            };
+            bail.bail(message).await;
+        });
+
+        self.set_body(body);
+        Ok(Halt(self))
+    }
+
+    /// Switches the connection of `req` to `protocol`, which the client has
+    /// to ask for in its `Upgrade` header, with `101 Switching Protocols`.
+    ///
+    /// Once the response has been sent, `handler` runs on a task of its own
+    /// with the raw connection and the server data. Call this from a route
+    /// handler, so the route params and any middleware before it, like
+    /// authentication, apply to the request before the switch. See
+    /// `upgrade_websocket` for WebSockets.
+    ///
+    /// Responds with `426 Upgrade Required` if the client didn't ask for
+    /// `protocol` over HTTP/1.1.
+    // like every handler, the error carries the response back
+    #[allow(clippy::result_large_err)]
+    pub fn upgrade<F, Fut>(mut self, req: &mut Request<D>, protocol: &str, handler: F) -> MiddlewareResult<D>
+    where F: FnOnce(Upgraded, Arc<D>) -> Fut + Send + 'static,
+          Fut: Future<Output = ()> + Send + 'static {
+        if !requests_upgrade(req, protocol) {
+            if let Ok(protocol) = HeaderValue::from_str(protocol) {
+                self.set_header(header::UPGRADE, protocol);
+            }
+            return self.error(StatusCode::UPGRADE_REQUIRED, format!("Expected an upgrade to {}", protocol));
+        }
+
+        let on_upgrade = upgrade::on(&mut req.origin);
+        let data = self.data.clone();
+        tokio::spawn(async move {
+            match on_upgrade.await {
+                Ok(upgraded) => handler(upgraded, data).await,
+                Err(e) => warn!("Failed to upgrade the connection: {}", e)
+            }
+        });
+
+        self.set(StatusCode::SWITCHING_PROTOCOLS);
+        self.set_header(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        // the client may have offered other protocols as well
+        if let Ok(protocol) = HeaderValue::from_str(protocol) {
+            self.set_header(header::UPGRADE, protocol);
+        }
+        self.origin.headers_mut().remove(header::CONTENT_TYPE);
+        Ok(Halt(self))
+    }
+
     // TODO: This needs to be more sophisticated to return the correct headers
     // not just "some headers" :)
     //
@@ -227,6 +336,103 @@ impl<D: Send + 'static + Sync> Response<D> {
         }
     }
 
+    /// Answers the request as if the client had requested `path`, which may
+    /// include a query string. The request runs through the middleware stack
+    /// again from the start, with everything but the uri unchanged.
+    ///
+    /// The stack is the one of the app the handler belongs to, so inside a
+    /// `Nickel` app mounted into another one `path` is relative to the
+    /// mount point. A request forwarded too often fails with a 500 error.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult, Middleware};
+    /// # use async_trait::async_trait;
+    ///
+    /// // A legacy URL alias
+    /// struct Alias(&'static str);
+    ///
+    /// #[async_trait]
+    /// impl Middleware<()> for Alias {
+    ///     async fn invoke(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+    ///         res.forward(req, self.0).await
+    ///     }
+    /// }
+    ///
+    /// let mut server = Nickel::new();
+    /// server.get("/old-about", Alias("/about"));
+    /// ```
+    pub async fn forward(mut self, req: &mut Request<D>, path: &str) -> MiddlewareResult<D> {
+        let stack = match self.stack.clone() {
+            Some(stack) => stack,
+            None => return self.error(StatusCode::INTERNAL_SERVER_ERROR, "Cannot forward outside of a middleware stack")
+        };
+        if req.forwards >= MAX_FORWARDS {
+            return self.error(StatusCode::INTERNAL_SERVER_ERROR, format!("Too many forwards, last to '{}'", path));
+        }
+        let uri = match path.parse::<Uri>() {
+            Ok(uri) if path.starts_with('/') => uri,
+            _ => return self.error(StatusCode::INTERNAL_SERVER_ERROR, format!("Invalid forward path '{}'", path))
+        };
+
+        let original = mem::replace(req.origin.uri_mut(), uri);
+        let route_result = req.route_result.take();
+        let mounted = req.mounted.take();
+        req.forwards += 1;
+
+        self.set(StatusCode::NOT_FOUND);
+        let res = match stack.dispatch(req, self).await {
+            Halt(res) | Action::Continue(res) => res
+        };
+
+        req.forwards -= 1;
+        *req.origin.uri_mut() = original;
+        req.route_result = route_result;
+        req.mounted = mounted;
+        Ok(Halt(res))
+    }
+
+    /// Runs `request` through the middleware stack as a separate request and
+    /// returns the response it got, e.g. to embed another route's output into
+    /// this one. See `forward` for the stack used.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Request, Response, MiddlewareResult};
+    /// use nickel::hyper::{Body, Request as HyperRequest, StatusCode};
+    ///
+    /// # #[allow(dead_code)]
+    /// async fn page(req: &mut Request, res: Response) -> MiddlewareResult {
+    ///     let sidebar = HyperRequest::get("/fragments/sidebar").body(Body::empty()).unwrap();
+    ///     let sidebar = match res.sub_request(req, sidebar).await {
+    ///         Ok(sidebar) => String::from_utf8_lossy(sidebar.body()).into_owned(),
+    ///         Err((status, message)) => return res.error(status, message)
+    ///     };
+    ///     res.send(format!("<main>...</main>{}", sidebar))
+    /// }
+    /// ```
+    pub async fn sub_request(&self, req: &Request<D>, request: HyperRequest<Body>)
+                             -> Result<HyperResponse<Bytes>, (StatusCode, String)> {
+        let stack = self.stack.clone().ok_or_else(|| {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot dispatch a sub-request outside of a middleware stack".to_string())
+        })?;
+        if req.forwards >= MAX_FORWARDS {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Too many nested sub-requests, last to '{}'", request.uri())));
+        }
+
+        let mut sub = Request::from_internal(request, req.remote_addr().cloned(), self.data.clone());
+        sub.forwards = req.forwards + 1;
+        let origin = HyperResponse::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
+        let res = Response::from_internal(origin, self.templates.clone(), self.data.clone()).with_stack(stack.clone());
+        let res = match stack.dispatch(&mut sub, res).await {
+            Halt(res) | Action::Continue(res) => res.origin
+        };
+
+        let (parts, body) = res.into_parts();
+        let body = body::to_bytes(body).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        Ok(HyperResponse::from_parts(parts, body))
+    }
+
     // Todo: migration cleanup
     //
     // hyper::Response no longer has a start() method. The api has
@@ -251,6 +457,11 @@ impl<D: Send + 'static + Sync> Response<D> {
         self.data.clone()
     }
 
+    // The templates, for rendering after the response has been handed on.
+    pub(crate) fn templates(&self) -> Arc<TemplateCache> {
+        self.templates.clone()
+    }
+
     // pub fn on_send<F>(&mut self, f: F)
     //         where F: FnMut(&mut Response<D>) + 'static {
     //     self.on_send.push(Box::new(f))
@@ -286,7 +497,8 @@ impl<D: Send + 'static + Sync> Response<D> {
     /// In the case of an unrecoverable error while a stream is already in
     /// progress, there is no standard way to signal to the client that an
     /// error has occurred. `bail` will drop the connection and log an error
-    /// message.
+    /// message. Streams written with `Response::stream` bail out through
+    /// `ResponseWriter::bail`.
     pub fn bail<T>(self, message: T) -> MiddlewareResult<D>
             where T: Into<Cow<'static, str>> {
         let _ = self.end();
@@ -328,6 +540,28 @@ impl <D: Send + 'static + Sync> Response<D> {
     pub fn extensions_mut(&mut self) -> &mut ShareMap {
         &mut self.map
     }
+
+    // Hands the response to an app with different server data, see `Nickel`
+    // as `Middleware`.
+    pub(crate) fn with_data<D2>(self, data: Arc<D2>, stack: Option<Arc<MiddlewareStack<D2>>>) -> Response<D2>
+    where D2: Send + 'static + Sync {
+        Response {
+            origin: self.origin,
+            templates: self.templates,
+            data,
+            map: self.map,
+            stack
+        }
+    }
+
+    pub(crate) fn stack(&self) -> Option<Arc<MiddlewareStack<D>>> {
+        self.stack.clone()
+    }
+
+    pub(crate) fn with_stack(mut self, stack: Arc<MiddlewareStack<D>>) -> Response<D> {
+        self.stack = Some(stack);
+        self
+    }
 }
 
 // TODO: migration cleanup - Extensible does not support ShareMap, but TypeMap is not Sync+Send
@@ -343,7 +577,20 @@ impl <D: Send + 'static + Sync> Response<D> {
 
 // impl<D: Send + 'static + Sync> Pluggable for Response<D> {}
 
-fn mime_from_filename<P: AsRef<Path>>(path: P) -> Option<MediaType> {
+// Whether `req` asks to switch to `protocol`.
+pub(crate) fn requests_upgrade<D>(req: &Request<D>, protocol: &str) -> bool {
+    let has_token = |name: header::HeaderName, token: &str| {
+        req.origin.headers().get_all(name).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    req.origin.version() == Version::HTTP_11
+        && has_token(header::CONNECTION, "upgrade")
+        && has_token(header::UPGRADE, protocol)
+}
+
+pub(crate) fn mime_from_filename<P: AsRef<Path>>(path: P) -> Option<MediaType> {
     path.as_ref()
         .extension()
         .and_then(|os| os.to_str())
@@ -376,3 +623,96 @@ mod modifier_impls {
         }
     }
 }
+
+#[tokio::test]
+async fn forwards_and_dispatches_sub_requests() {
+    use async_trait::async_trait;
+    use hyper::Method;
+    use crate::{HttpRouter, Middleware, Router};
+    use crate::default_error_handler::DefaultErrorHandler;
+    use crate::test_util::{request, run_shared};
+
+    struct Forward(&'static str);
+
+    #[async_trait]
+    impl Middleware<()> for Forward {
+        async fn invoke(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            res.forward(req, self.0).await
+        }
+    }
+
+    struct Page;
+
+    #[async_trait]
+    impl Middleware<()> for Page {
+        async fn invoke(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            let fragment = HyperRequest::get("/fragment?n=2").body(Body::empty()).unwrap();
+            match res.sub_request(req, fragment).await {
+                Ok(fragment) => {
+                    let body = format!("page {} {}", fragment.status(), String::from_utf8_lossy(fragment.body()));
+                    res.send(body)
+                },
+                Err((status, message)) => res.error(status, message)
+            }
+        }
+    }
+
+    let mut router = Router::new();
+    router.get("/about", middleware! { |request| format!("about {}", request.origin.uri()) });
+    router.get("/old-about", Forward("/about?from=old"));
+    router.get("/loop", Forward("/loop"));
+    router.get("/fragment", middleware! { |request| format!("fragment {}", request.origin.uri()) });
+    router.get("/page", Page);
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(DefaultErrorHandler);
+    stack.add_middleware(router);
+    let stack = Arc::new(stack);
+
+    let run = |uri| run_shared(&stack, request(Method::GET, uri, Arc::new(())));
+    assert_eq!(run("/old-about").await, (StatusCode::OK, "about /about?from=old".to_string()));
+    assert_eq!(run("/loop").await.0, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(run("/page").await, (StatusCode::OK, "page 200 OK fragment /fragment?n=2".to_string()));
+}
+
+#[tokio::test]
+async fn streams_bodies() {
+    use crate::test_util::response;
+
+    let res = response(Arc::new(())).stream(|mut writer| async move {
+        for i in 0..3 {
+            writer.write(format!("chunk {}\n", i)).await?;
+        }
+        Ok::<_, io::Error>(())
+    });
+    let body = match res {
+        Ok(Halt(res)) => body::to_bytes(res.origin.into_body()).await.unwrap(),
+        _ => panic!("expected the response to halt")
+    };
+    assert_eq!(&body[..], b"chunk 0\nchunk 1\nchunk 2\n");
+
+    let res = response(Arc::new(())).stream(|mut writer| async move {
+        writer.write("partial").await.map_err(|e| e.to_string())?;
+        Err("the report failed".to_string())
+    });
+    match res {
+        Ok(Halt(res)) => assert!(body::to_bytes(res.origin.into_body()).await.is_err()),
+        _ => panic!("expected the response to halt")
+    }
+
+    // an unread body holds the writer back until the client goes away
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let written = Arc::new(AtomicUsize::new(0));
+    let counter = written.clone();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let res = response(Arc::new(())).stream(|mut writer| async move {
+        while writer.write("data").await.is_ok() {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+        tx.send(writer.is_closed()).unwrap();
+        Ok::<_, io::Error>(())
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert!(written.load(Ordering::SeqCst) <= 2, "wrote {:?} chunks without a reader", written);
+    drop(res);
+    assert!(rx.await.unwrap());
+}
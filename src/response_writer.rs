@@ -0,0 +1,56 @@
+//! Writing a response body incrementally, see `Response::stream`.
+use std::borrow::Cow;
+use std::io;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use hyper::Body;
+use hyper::body::Bytes;
+
+// How many chunks may wait for the client before writing blocks.
+const BUFFERED_CHUNKS: usize = 1;
+
+/// Writes the body of a streamed response in chunks, see `Response::stream`.
+///
+/// Writing waits while the client hasn't caught up with the chunks written
+/// before, so a slow client slows the writer down instead of the chunks
+/// piling up in memory.
+pub struct ResponseWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ResponseWriter {
+    // A writer and the body it writes to.
+    pub(crate) fn new() -> (ResponseWriter, Body) {
+        let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+        (ResponseWriter { sender }, Body::wrap_stream(receiver))
+    }
+
+    // Another writer to the same body, which ends once all writers are gone.
+    pub(crate) fn another(&self) -> ResponseWriter {
+        ResponseWriter { sender: self.sender.clone() }
+    }
+
+    /// Sends `chunk` to the client.
+    ///
+    /// # Errors
+    /// Fails if the client has gone away.
+    pub async fn write<T: Into<Bytes>>(&mut self, chunk: T) -> io::Result<()> {
+        self.sender.send(Ok(chunk.into())).await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client has gone away"))
+    }
+
+    /// Whether the client has gone away, so further writes would fail.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Like `Response::bail`, for an unrecoverable error after the response
+    /// has started. The connection is dropped, so the client can tell that
+    /// the response is incomplete, and `message` is logged.
+    pub async fn bail<T>(mut self, message: T)
+            where T: Into<Cow<'static, str>> {
+        let message = message.into();
+        error!("Bailing out of a streamed response: {}", message);
+        let _ = self.sender.send(Err(io::Error::other(message))).await;
+    }
+}
@@ -0,0 +1,388 @@
+//! Rewriting and redirecting requests by an ordered list of rules.
+use std::error::Error as StdError;
+use std::{fmt, fs, io};
+use std::path::Path;
+use async_trait::async_trait;
+use hyper::StatusCode;
+use regex::Regex;
+use crate::extensions::Redirect;
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mount::{mount_state, with_path};
+use crate::request::Request;
+use crate::response::Response;
+use crate::virtual_hosts::request_host;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RuleAction {
+    Rewrite,
+    Redirect(StatusCode),
+}
+
+enum Condition {
+    Host(Regex),
+    Scheme(String),
+    Query(Regex),
+}
+
+/// A rule of `Rewrite`, matching the request path with a regex and replacing
+/// it with a target, which may refer to the captures of the regex as `$1`,
+/// `${1}` or `${name}`.
+///
+/// The query string of the request is kept, unless the target has one of its
+/// own.
+pub struct Rule {
+    pattern: Regex,
+    target: String,
+    action: RuleAction,
+    conditions: Vec<Condition>,
+    last: bool,
+}
+
+impl Rule {
+    /// Rewrites the path internally, the following middleware sees the target
+    /// as the requested path.
+    pub fn rewrite<S: Into<String>>(pattern: Regex, target: S) -> Rule {
+        Rule::new(pattern, target.into(), RuleAction::Rewrite)
+    }
+
+    /// Redirects the client to the target with `status`, which has to be a
+    /// redirection like `301 Moved Permanently`, `302 Found` or
+    /// `308 Permanent Redirect`. The target may be a path or a full URL.
+    ///
+    /// # Panics
+    /// Panics if `status` isn't a redirection.
+    pub fn redirect<S: Into<String>>(pattern: Regex, target: S, status: StatusCode) -> Rule {
+        assert!(status.is_redirection(), "A redirect rule needs a 3xx status, not {}", status);
+        Rule::new(pattern, target.into(), RuleAction::Redirect(status))
+    }
+
+    fn new(pattern: Regex, target: String, action: RuleAction) -> Rule {
+        Rule {
+            pattern,
+            target,
+            action,
+            conditions: Vec::new(),
+            last: false
+        }
+    }
+
+    /// Only applies the rule to requests for a host matching `regex`. The
+    /// host is matched without port and in lower case.
+    pub fn host(mut self, regex: Regex) -> Self {
+        self.conditions.push(Condition::Host(regex));
+        self
+    }
+
+    /// Only applies the rule to requests made over `scheme`, as told by the
+    /// `X-Forwarded-Proto` header of a proxy or the request uri, and `http`
+    /// otherwise.
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.conditions.push(Condition::Scheme(scheme.to_ascii_lowercase()));
+        self
+    }
+
+    /// Only applies the rule to requests with a query string matching
+    /// `regex`. A request without a query string has an empty one.
+    pub fn query(mut self, regex: Regex) -> Self {
+        self.conditions.push(Condition::Query(regex));
+        self
+    }
+
+    /// Stops at this rule if it applies, skipping the rules after it.
+    /// Redirect rules always stop.
+    pub fn last(mut self) -> Self {
+        self.last = true;
+        self
+    }
+
+    fn applies<D>(&self, req: &Request<D>) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Host(regex) => request_host(req).is_some_and(|host| regex.is_match(&host)),
+            Condition::Scheme(scheme) => request_scheme(req) == *scheme,
+            Condition::Query(regex) => regex.is_match(req.origin.uri().query().unwrap_or(""))
+        })
+    }
+
+    // The target for `path` if the rule matches it.
+    fn target_for(&self, path: &str) -> Option<String> {
+        let captures = self.pattern.captures(path)?;
+        let mut target = String::new();
+        captures.expand(&self.target, &mut target);
+        Some(target)
+    }
+}
+
+fn request_scheme<D>(req: &Request<D>) -> String {
+    let forwarded = req.origin.headers().get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .map(str::trim);
+    forwarded.or_else(|| req.origin.uri().scheme_str())
+             .unwrap_or("http")
+             .to_ascii_lowercase()
+}
+
+/// The error returned for an invalid rules file.
+#[derive(Debug)]
+pub struct RewriteError {
+    line: usize,
+    reason: String
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "Invalid rewrite rules: {}", self.reason)
+        } else {
+            write!(f, "Invalid rewrite rule on line {}: {}", self.line, self.reason)
+        }
+    }
+}
+
+impl StdError for RewriteError { }
+
+impl From<io::Error> for RewriteError {
+    fn from(err: io::Error) -> RewriteError {
+        RewriteError { line: 0, reason: err.to_string() }
+    }
+}
+
+/// Rewrites request paths internally or redirects the client, driven by an
+/// ordered list of `Rule`s. This is useful to keep old URLs working or to
+/// enforce a canonical form of them.
+///
+/// The rules are tried in order against the request path. Every rewrite rule
+/// which applies changes the path the rules after it see, and that the
+/// following middleware see. The first redirect rule which applies ends the
+/// request. The path as requested stays available through
+/// `Request::original_uri`. Redirects to a path stay on the same host, as
+/// the slashes and backslashes a path starts with are collapsed into one.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, Rewrite, Rule};
+/// use nickel::status::StatusCode;
+/// use regex::Regex;
+///
+/// let mut server = Nickel::new();
+/// server.utilize(Rewrite::new()
+///     // a canonical host
+///     .rule(Rule::redirect(Regex::new("^(.*)$").unwrap(), "https://www.example.com$1",
+///                          StatusCode::MOVED_PERMANENTLY)
+///               .host(Regex::new("^example\\.com$").unwrap()))
+///     // no trailing slashes
+///     .rule(Rule::redirect(Regex::new("^/(.+)/$").unwrap(), "/$1", StatusCode::PERMANENT_REDIRECT))
+///     .rule(Rule::rewrite(Regex::new("^/blog/(?P<slug>[^/]+)$").unwrap(), "/posts/${slug}")));
+/// ```
+#[derive(Default)]
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    pub fn new() -> Rewrite {
+        Rewrite { rules: Vec::new() }
+    }
+
+    /// Adds `rule` after the rules added so far.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Loads the rules from a file, see `Rewrite::parse` for its format.
+    ///
+    /// # Errors
+    /// Fails if the file can't be read or has an invalid rule.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Rewrite, RewriteError> {
+        Rewrite::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses rules, one per line, like
+    ///
+    /// ```text
+    /// # comments and blank lines are ignored
+    /// rewrite ^/blog/(.*)$ /posts/$1
+    /// redirect 301 ^(.*)$ https://www.example.com$1 host=^example\.com$
+    /// redirect 308 ^/old/(.*)$ /new/$1 scheme=https query=^id= last
+    /// ```
+    ///
+    /// A rule is its action, the status for a redirect, the pattern and the
+    /// target, followed by any conditions `host=`, `scheme=` and `query=` and
+    /// the `last` flag. Parts are separated by whitespace, so patterns use
+    /// `\s` instead.
+    ///
+    /// # Errors
+    /// Fails at the first invalid rule.
+    pub fn parse(rules: &str) -> Result<Rewrite, RewriteError> {
+        let mut rewrite = Rewrite::new();
+        for (i, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|reason| RewriteError { line: i + 1, reason })?;
+            rewrite = rewrite.rule(rule);
+        }
+        Ok(rewrite)
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let mut parts = line.split_whitespace();
+    let action = match parts.next() {
+        Some("rewrite") => RuleAction::Rewrite,
+        Some("redirect") => {
+            let status = parts.next().ok_or("A redirect needs a status")?;
+            let status = status.parse::<StatusCode>()
+                .ok()
+                .filter(StatusCode::is_redirection)
+                .ok_or_else(|| format!("'{}' isn't a redirect status", status))?;
+            RuleAction::Redirect(status)
+        },
+        Some(other) => return Err(format!("Unknown action '{}'", other)),
+        None => unreachable!("blank lines are skipped")
+    };
+    let pattern = parts.next().ok_or("A rule needs a pattern")?;
+    let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+    let target = parts.next().ok_or("A rule needs a target")?;
+
+    let mut rule = Rule::new(pattern, target.to_string(), action);
+    for part in parts {
+        let regex = |value: &str| Regex::new(value).map_err(|e| e.to_string());
+        rule = match part.split_once('=') {
+            Some(("host", value)) => rule.host(regex(value)?),
+            Some(("scheme", value)) => rule.scheme(value),
+            Some(("query", value)) => rule.query(regex(value)?),
+            None if part == "last" => rule.last(),
+            _ => return Err(format!("Unknown condition '{}'", part))
+        };
+    }
+    Ok(rule)
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Rewrite {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        let mut rewritten = None;
+
+        for rule in &self.rules {
+            let target = match rule.target_for(req.path_without_query()) {
+                Some(target) if rule.applies(req) => target,
+                _ => continue
+            };
+
+            let (path, query) = match target.split_once('?') {
+                Some((path, query)) => (path.to_string(), Some(query.to_string())),
+                None => (target, req.origin.uri().query().map(str::to_string))
+            };
+
+            if let RuleAction::Redirect(status) = rule.action {
+                // paths are relative to the enclosing mounts, the client needs
+                // them as requested. A leading `//` or `/\` would make the
+                // client take the path for another host.
+                let mut location = if path.starts_with('/') {
+                    let path = path.trim_start_matches(['/', '\\']);
+                    format!("{}/{}", req.base_path(), path)
+                } else {
+                    path
+                };
+                if let Some(query) = query {
+                    location = format!("{}?{}", location, query);
+                }
+                return res.redirect_with(location, status);
+            }
+
+            let uri = match with_path(req.origin.uri(), &path, query.as_deref()) {
+                Some(uri) => uri,
+                None => return res.error(StatusCode::INTERNAL_SERVER_ERROR,
+                                         format!("Rewrite rule {} gave the invalid path '{}'", rule.pattern, path))
+            };
+            rewritten.get_or_insert_with(|| mount_state(req));
+            *req.origin.uri_mut() = uri;
+
+            if rule.last {
+                break;
+            }
+        }
+
+        if rewritten.is_some() {
+            req.mounted = rewritten;
+        }
+        res.next_middleware()
+    }
+}
+
+#[tokio::test]
+async fn rewrites_and_redirects() {
+    use std::sync::Arc;
+    use hyper::Method;
+    use hyper::header::{self, HeaderValue};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, response, run_stack};
+
+    let rules = Rewrite::parse(r"
+        # legacy urls
+        redirect 301 ^(.*)$ https://www.example.com$1 host=^example\.com$
+        redirect 308 ^/(.+)/$ /$1
+        redirect 301 ^(.+)/index\.html$ $1/
+        rewrite ^/blog/(?P<slug>[^/]+)$ /posts/${slug}
+        rewrite ^/posts/(.*)$ /articles/$1 query=^draft last
+        rewrite ^/articles/(.*)$ /archive/$1
+        redirect 302 ^/login$ /login?secure=1 scheme=http
+    ").unwrap();
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(rules);
+    stack.add_middleware(middleware! { |request|
+        format!("{} from {}", request.origin.uri(), request.original_uri())
+    });
+
+    let run = |stack, uri, host: Option<&'static str>| {
+        let mut req = request(Method::GET, uri, Arc::new(()));
+        if let Some(host) = host {
+            req.origin.headers_mut().insert(header::HOST, HeaderValue::from_static(host));
+        }
+        run_stack(stack, req)
+    };
+
+    assert_eq!(run(&stack, "/blog/hello", None).await.1, "/posts/hello from /blog/hello");
+    assert_eq!(run(&stack, "/blog/hello?page=2", None).await.1, "/posts/hello?page=2 from /blog/hello?page=2");
+    assert_eq!(run(&stack, "/blog/hello?draft=1", None).await.1, "/articles/hello?draft=1 from /blog/hello?draft=1");
+    assert_eq!(run(&stack, "/articles/x", None).await.1, "/archive/x from /articles/x");
+    assert_eq!(run(&stack, "/other", Some("www.example.com")).await.1, "/other from /other");
+
+    let redirect = |uri, host: &'static str, proto: Option<&'static str>| {
+        let stack = &stack;
+        async move {
+            let mut req = request(Method::GET, uri, Arc::new(()));
+            req.origin.headers_mut().insert(header::HOST, HeaderValue::from_static(host));
+            if let Some(proto) = proto {
+                req.origin.headers_mut().insert("x-forwarded-proto", HeaderValue::from_static(proto));
+            }
+            let res = stack.invoke(req, response(Arc::new(()))).await;
+            let location = res.headers().get(header::LOCATION).map(|l| l.to_str().unwrap().to_string());
+            (res.status(), location)
+        }
+    };
+
+    let expect = |status, location: &str| (status, Some(location.to_string()));
+    assert_eq!(redirect("/a/b?c=d", "Example.com:80", None).await,
+               expect(StatusCode::MOVED_PERMANENTLY, "https://www.example.com/a/b?c=d"));
+    assert_eq!(redirect("/a/b/", "www.example.com", None).await, expect(StatusCode::PERMANENT_REDIRECT, "/a/b"));
+    assert_eq!(redirect("//evil.com/", "www.example.com", None).await, expect(StatusCode::PERMANENT_REDIRECT, "/evil.com"));
+    assert_eq!(redirect("/\\evil.com/index.html", "www.example.com", None).await,
+               expect(StatusCode::MOVED_PERMANENTLY, "/evil.com/"));
+    assert_eq!(redirect("/login?next=/", "www.example.com", None).await, expect(StatusCode::FOUND, "/login?secure=1"));
+    assert_eq!(redirect("/login", "www.example.com", Some("https")).await, (StatusCode::NOT_FOUND, None));
+}
+
+#[test]
+fn parse_errors() {
+    let err = Rewrite::parse("rewrite ^/a$ /b\nredirect 200 ^/a$ /b").err().unwrap();
+    assert_eq!(err.to_string(), "Invalid rewrite rule on line 2: '200' isn't a redirect status");
+    assert!(Rewrite::parse("rewrite ^/a$").is_err());
+    assert!(Rewrite::parse("rewrite ^/(a$ /b").is_err());
+    assert!(Rewrite::parse("rewrite ^/a$ /b method=GET").is_err());
+    assert!(Rewrite::parse("proxy ^/a$ /b").is_err());
+}
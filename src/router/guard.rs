@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use hyper::header::{self, HeaderName};
+use mime::Mime;
+use regex::Regex;
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::urlencoded;
+use crate::virtual_hosts::{request_authority, split_host};
+
+/// A condition a request has to meet, besides method and path, for a route to
+/// match it. Guards are attached to the most recently added route with
+/// `Router::guard`, or to all routes of the most recently added resource. If any of them fails, the router moves on to the next
+/// route matching the request.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, MediaType};
+/// use nickel::router::Guard;
+///
+/// fn main() {
+///     let mut router = Nickel::router();
+///
+///     router.post("/upload", middleware! { "json upload" })
+///           .guard(Guard::content_type(MediaType::Json));
+///     router.post("/upload", middleware! { "xml upload" })
+///           .guard(Guard::content_type(MediaType::Xml));
+///
+///     router.get("/search", middleware! { "new search" })
+///           .guard(Guard::header("x-feature", "new-search"));
+///     router.get("/search", middleware! { "search" });
+/// }
+/// ```
+pub struct Guard<D = ()> {
+    description: String,
+    check: Check<D>,
+}
+
+type Check<D> = Arc<dyn Fn(&Request<D>) -> bool + Send + Sync>;
+
+// by hand, a derive would require `D: Clone`
+impl<D> Clone for Guard<D> {
+    fn clone(&self) -> Self {
+        Guard { description: self.description.clone(), check: self.check.clone() }
+    }
+}
+
+impl<D> Guard<D> {
+    /// A guard calling `check`, `description` is shown in the route table.
+    pub fn new<S, F>(description: S, check: F) -> Guard<D>
+    where S: Into<String>, F: Fn(&Request<D>) -> bool + Send + Sync + 'static {
+        Guard {
+            description: description.into(),
+            check: Arc::new(check)
+        }
+    }
+
+    /// Requires the header `name` to be present with exactly `value`.
+    ///
+    /// # Panics
+    /// Panics if `name` isn't a valid header name.
+    pub fn header(name: &str, value: &str) -> Guard<D> {
+        let header = header_name(name);
+        let value = value.to_string();
+        Guard::new(format!("header {} = {}", name, value), move |req| {
+            req.origin.headers().get_all(&header).iter().any(|v| v == &value[..])
+        })
+    }
+
+    /// Requires the header `name` to be present with a value matching
+    /// `regex`.
+    ///
+    /// # Panics
+    /// Panics if `name` isn't a valid header name.
+    pub fn header_matches(name: &str, regex: Regex) -> Guard<D> {
+        let header = header_name(name);
+        Guard::new(format!("header {} ~ {}", name, regex), move |req| {
+            req.origin.headers().get_all(&header).iter()
+                .any(|v| v.to_str().is_ok_and(|v| regex.is_match(v)))
+        })
+    }
+
+    /// Requires the request to be for `host`, compared without case. The
+    /// port is ignored unless `host` has one.
+    pub fn host(host: &str) -> Guard<D> {
+        let (name, port) = split_host(host);
+        let port = port.map(str::to_string);
+        Guard::new(format!("host {}", host.to_ascii_lowercase()), move |req| {
+            request_authority(req).is_some_and(|authority| {
+                let (requested, requested_port) = split_host(authority);
+                requested == name && (port.is_none() || requested_port == port.as_deref())
+            })
+        })
+    }
+
+    /// Requires the query string param `name` to be present.
+    pub fn query(name: &str) -> Guard<D> {
+        let name = name.to_string();
+        Guard::new(format!("query {}", name), move |req| {
+            urlencoded::parse_uri(req.origin.uri()).get(&name).is_some()
+        })
+    }
+
+    /// Requires the query string param `name` to be present with `value`.
+    pub fn query_equals(name: &str, value: &str) -> Guard<D> {
+        let (name, value) = (name.to_string(), value.to_string());
+        Guard::new(format!("query {} = {}", name, value), move |req| {
+            let query = urlencoded::parse_uri(req.origin.uri());
+            query.all(&name).is_some_and(|values| values.contains(&value))
+        })
+    }
+
+    /// Requires the request body to be of `media_type`, params like
+    /// `charset` are ignored.
+    pub fn content_type(media_type: MediaType) -> Guard<D> {
+        let expected = Mime::from(media_type);
+        Guard::new(format!("content type {}", expected), move |req| {
+            req.origin.headers().get(header::CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse::<Mime>().ok())
+                .is_some_and(|mime| mime.essence_str() == expected.essence_str())
+        })
+    }
+
+    /// Whether `req` passes the guard.
+    pub fn check(&self, req: &Request<D>) -> bool {
+        (self.check)(req)
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+fn header_name(name: &str) -> HeaderName {
+    HeaderName::from_bytes(name.as_bytes())
+        .unwrap_or_else(|_| panic!("Invalid header name for a guard: {}", name))
+}
+
+#[test]
+fn host_guards_parse_hosts_like_virtual_hosts() {
+    use hyper::Method;
+    use hyper::header::HeaderValue;
+    use crate::test_util::request;
+
+    let check = |guard: &Guard<()>, host: &'static str| {
+        let mut req = request(Method::GET, "/", Arc::new(()));
+        req.origin.headers_mut().insert(header::HOST, HeaderValue::from_static(host));
+        guard.check(&req)
+    };
+
+    let guard = Guard::host("Example.com");
+    assert!(check(&guard, "example.com"));
+    assert!(check(&guard, "EXAMPLE.com.:8080"));
+    assert!(!check(&guard, "example.com.evil"));
+
+    let guard = Guard::host("example.com:8080");
+    assert!(check(&guard, "example.com:8080"));
+    assert!(!check(&guard, "example.com:9090"));
+    assert!(!check(&guard, "example.com"));
+
+    let guard = Guard::host("[::1]");
+    assert!(check(&guard, "[::1]:8080"));
+    assert!(!check(&guard, "[::2]"));
+}
@@ -1,6 +1,8 @@
+use std::sync::Arc;
 use hyper::Method;
 use crate::middleware::Middleware;
-use crate::router::Matcher;
+use crate::router::{Matcher, Resource};
+use crate::router::resource::{self, NotAllowed, ResourceHandler};
 
 pub trait HttpRouter<D: Send + 'static + Sync> {
     /// Registers a handler to be used for a specified method.
@@ -72,6 +74,12 @@ pub trait HttpRouter<D: Send + 'static + Sync> {
     ///         "This matches /user/list/4711 and also /user/extended/list/4711"
     ///     });
     ///
+    ///     // with a named double wildcard
+    ///     server.get("/files/*path", middleware! { |request|
+    ///         format!("This matches /files/docs/a.txt with path: {}",
+    ///                 request.param("path").unwrap())
+    ///     });
+    ///
     ///     // with chained routes
     ///     server
     ///         .get("/foo", middleware! {
@@ -180,4 +188,64 @@ pub trait HttpRouter<D: Send + 'static + Sync> {
     fn patch<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
         self.add_route(Method::PATCH, matcher, handler)
     }
+
+    /// Registers the conventional RESTful routes for `resource` under `path`:
+    ///
+    /// | Route               | Action    |
+    /// |---------------------|-----------|
+    /// | `GET /path`         | `index`   |
+    /// | `POST /path`        | `create`  |
+    /// | `GET /path/:id`     | `show`    |
+    /// | `PUT /path/:id`     | `update`  |
+    /// | `PATCH /path/:id`   | `update`  |
+    /// | `DELETE /path/:id`  | `destroy` |
+    ///
+    /// Only the routes of the actions the resource lists are registered,
+    /// the other methods get `405 Method Not Allowed` at both paths. A guard
+    /// added right after applies to all of these routes.
+    ///
+    /// Nested resources are registered with a param in `path`, e.g.
+    /// `/users/:user_id/posts`, or in a scope.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// # use async_trait::async_trait;
+    /// use nickel::{Nickel, HttpRouter, Resource};
+    /// # struct Users;
+    /// # struct Posts;
+    /// # use nickel::ResourceAction;
+    /// # #[async_trait] impl Resource for Users { fn actions(&self) -> &'static [ResourceAction] { &[] } }
+    /// # #[async_trait] impl Resource for Posts { fn actions(&self) -> &'static [ResourceAction] { &[] } }
+    ///
+    /// let mut router = Nickel::router();
+    /// router.resource("/users", Users)
+    ///       .scope("/users/:user_id", |user| { user.resource("/posts", Posts); });
+    /// ```
+    fn resource<R: Resource<D>>(&mut self, path: &str, resource: R) -> &mut Self {
+        let collection = path.trim_end_matches('/');
+        let member = format!("{}/:id", collection);
+        let actions = resource.actions();
+        let resource = Arc::new(resource);
+
+        let mut count = 0;
+        for (path, on_member) in [(collection, false), (&member[..], true)] {
+            let (served, not_allowed, allow) = resource::routes(actions, on_member);
+            for (method, action) in served {
+                self.add_route(method, path, ResourceHandler { resource: resource.clone(), action });
+                count += 1;
+            }
+            for method in not_allowed {
+                self.add_route(method, path, NotAllowed { allow: allow.clone() });
+                count += 1;
+            }
+        }
+        self.group(count)
+    }
+
+    /// Marks the most recently added `count` routes as added together, so
+    /// that a guard added next applies to all of them.
+    #[doc(hidden)]
+    fn group(&mut self, _count: usize) -> &mut Self {
+        self
+    }
 }
@@ -1,5 +1,5 @@
 use super::Matcher;
-use regex::{Regex, Captures};
+use regex::Regex;
 
 impl From<Regex> for Matcher {
     fn from(regex: Regex) -> Matcher {
@@ -15,45 +15,104 @@ impl<'a> From<&'a str> for Matcher {
 }
 
 lazy_static! {
-    static ref REGEX_VAR_SEQ: Regex = Regex::new(r":([,a-zA-Z0-9_-]*)").unwrap();
+    // Matches `:name` params, `*name` named tail captures and the anonymous
+    // `*` and `**` wildcards.
+    static ref REGEX_VAR_SEQ: Regex =
+        Regex::new(r":([,a-zA-Z0-9_-]*)|\*\*?([a-zA-Z_][a-zA-Z0-9_]*)|\*\*|\*").unwrap();
 }
 
-pub static FORMAT_PARAM:      &'static str = "format";
+pub static FORMAT_PARAM:      &str = "format";
 // FIXME: Once const fn lands this could be defined in terms of the above
-static FORMAT_VAR:            &'static str = ":format";
-static VAR_SEQ:               &'static str = "[,a-zA-Z0-9_-]*";
-static VAR_SEQ_WITH_SLASH:    &'static str = "[,/a-zA-Z0-9_-]*";
-// matches request params (e.g. ?foo=true&bar=false)
-static REGEX_PARAM_SEQ:       &'static str = "(\\?[a-zA-Z0-9%_=&-]*)?";
+static FORMAT_VAR:            &str = ":format";
+pub static FORMAT_SUFFIX:     &str = "(\\.:format)?";
+// Every character allowed in a path segment by RFC 3986 (`pchar`), plus
+// non-ASCII text for paths which have already been percent-decoded.
+static SEGMENT_CHARS:         &str = "-a-zA-Z0-9._~!$&'()*+,;=:@%\\x{80}-\\x{10FFFF}";
+// The same without `.`, so a `:format` suffix only takes the last extension
+static FORMAT_CHARS:          &str = "-a-zA-Z0-9_~!$&'()*+,;=:@%\\x{80}-\\x{10FFFF}";
+// Accepts any query string (e.g. ?foo=true&bar=false). The query isn't part of
+// routing, conditions on it are expressed with a `Guard`.
+static REGEX_PARAM_SEQ:       &str = "(?:\\?.*)?";
 
 impl From<String> for Matcher {
     fn from(s: String) -> Matcher {
         let with_format = if s.contains(FORMAT_VAR) {
             s
         } else {
-            format!("{}(\\.{})?", s, FORMAT_VAR)
+            format!("{}{}", s, FORMAT_SUFFIX)
         };
 
-        // First mark all double wildcards for replacement. We can't directly
-        // replace them since the replacement does contain the * symbol as well,
-        // which would get overwritten with the next replace call
-        let with_placeholder = with_format.replace("**", "___DOUBLE_WILDCARD___");
-
-        // Then replace the regular wildcard symbols (*) with the appropriate regex
-        let star_replaced = with_placeholder.replace("*", VAR_SEQ);
-
-        // Now replace the previously marked double wild cards (**)
-        let wildcarded = star_replaced.replace("___DOUBLE_WILDCARD___", VAR_SEQ_WITH_SLASH);
+        // The implicit format suffix is compiled separately so that it only
+        // takes the last extension, e.g. `:file` in `/:file` gets `a.tar` and
+        // `format` gets `gz` for `/a.tar.gz`.
+        let (stem, format_suffix) = if with_format.ends_with(FORMAT_SUFFIX) {
+            (&with_format[..with_format.len() - FORMAT_SUFFIX.len()], true)
+        } else {
+            (&with_format[..], false)
+        };
 
-        // Add a named capture for each :(variable) symbol
-        let named_captures = REGEX_VAR_SEQ.replace_all(&wildcarded, |captures: &Captures<'_>| {
-            // There should only ever be one match (after subgroup 0)
-            let c = captures.iter().skip(1).next().unwrap();
-            format!("(?P<{}>[,a-zA-Z0-9%_-]*)", c.unwrap().as_str())
+        // A named tail at the very end of the path keeps the extension, the
+        // format is captured from inside of it instead. This way
+        // `/files/*path` gives `path` = `docs/a.txt` and `format` = `txt`.
+        let tail_format = format_suffix && REGEX_VAR_SEQ.captures_iter(stem).last().is_some_and(|c| {
+            c.get(2).is_some() && c.get(0).unwrap().end() == stem.len()
         });
 
-        let line_regex = format!("^{}{}$", named_captures, REGEX_PARAM_SEQ);
+        let named_captures = compile_stem(stem, tail_format);
+        let format = if format_suffix && !tail_format { format_capture() } else { String::new() };
+        let line_regex = format!("^{}{}{}$", named_captures, format, REGEX_PARAM_SEQ);
         let regex = Regex::new(&line_regex).unwrap();
-        Matcher::new(with_format, regex)
+        Matcher::from_pattern(with_format, regex)
+    }
+}
+
+impl Matcher {
+    /// Nests the matcher under `prefix`, which may contain params and
+    /// wildcards just like a route pattern.
+    pub fn with_prefix(&self, prefix: &str) -> Matcher {
+        let prefix = prefix.trim_end_matches('/');
+        if self.is_pattern() {
+            Matcher::from(format!("{}{}", prefix, self.path()))
+        } else {
+            let regex = self.as_str().trim_start_matches('^');
+            let prefixed = format!("^{}{}", compile_prefix(prefix), regex);
+            Matcher::from(Regex::new(&prefixed).unwrap())
+        }
+    }
+}
+
+/// Compiles a path prefix, which may contain params and wildcards like a route
+/// pattern, to an unanchored regex.
+pub(crate) fn compile_prefix(prefix: &str) -> String {
+    compile_stem(prefix, false)
+}
+
+// Replaces wildcards and params with their regex, and escapes the literal
+// text around them, so e.g. the `.` in `/v1.0` only matches a dot.
+fn compile_stem(stem: &str, tail_format: bool) -> String {
+    let mut compiled = String::new();
+    let mut literal_start = 0;
+    for captures in REGEX_VAR_SEQ.captures_iter(stem) {
+        let token = captures.get(0).unwrap();
+        compiled.push_str(&regex::escape(&stem[literal_start..token.start()]));
+        literal_start = token.end();
+
+        let replacement = if let Some(param) = captures.get(1) {
+            format!("(?P<{}>[{}]*?)", param.as_str(), SEGMENT_CHARS)
+        } else if let Some(tail) = captures.get(2) {
+            let format = if tail_format { format_capture() } else { String::new() };
+            format!("(?P<{}>[{}/]*?{})", tail.as_str(), SEGMENT_CHARS, format)
+        } else if token.as_str() == "**" {
+            format!("[{}/]*?", SEGMENT_CHARS)
+        } else {
+            format!("[{}]*?", SEGMENT_CHARS)
+        };
+        compiled.push_str(&replacement);
     }
+    compiled.push_str(&regex::escape(&stem[literal_start..]));
+    compiled
+}
+
+fn format_capture() -> String {
+    format!("(?:\\.(?P<{}>[{}]*))?", FORMAT_PARAM, FORMAT_CHARS)
 }
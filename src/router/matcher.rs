@@ -1,23 +1,55 @@
 use std::borrow::Cow;
 use std::ops::Deref;
-use regex::Regex;
+use std::sync::OnceLock;
+use regex::{Regex, RegexBuilder};
 
 pub struct Matcher {
     path: Cow<'static, str>,
-    regex: Regex
+    regex: Regex,
+    // Whether `path` is a route pattern (e.g. `/users/:id`) rather than an
+    // opaque regex, see `route_table::find_conflicts`.
+    is_pattern: bool,
+    // `regex` ignoring case, built when first needed.
+    ignoring_case: OnceLock<Regex>
 }
 
 impl Matcher {
     pub fn new<P: Into<Cow<'static, str>>>(path: P, regex: Regex) -> Matcher {
         Matcher {
             path: path.into(),
-            regex: regex
+            regex: regex,
+            is_pattern: false,
+            ignoring_case: OnceLock::new()
+        }
+    }
+
+    pub(super) fn from_pattern<P: Into<Cow<'static, str>>>(path: P, regex: Regex) -> Matcher {
+        Matcher {
+            is_pattern: true,
+            ..Matcher::new(path, regex)
         }
     }
 
     pub fn path(&self) -> &str {
         &self.path
     }
+
+    pub(crate) fn is_pattern(&self) -> bool {
+        self.is_pattern
+    }
+
+    /// The regex to match with, ignoring case if `ignore_case` is set.
+    pub(crate) fn regex(&self, ignore_case: bool) -> &Regex {
+        if !ignore_case {
+            return &self.regex;
+        }
+        self.ignoring_case.get_or_init(|| {
+            RegexBuilder::new(self.regex.as_str())
+                .case_insensitive(true)
+                .build()
+                .expect("the regex compiled before")
+        })
+    }
 }
 
 impl Deref for Matcher {
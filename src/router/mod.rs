@@ -3,8 +3,17 @@ pub use self::http_router::HttpRouter;
 pub use self::router::{Router, Route, RouteResult};
 pub use self::matcher::Matcher;
 pub use self::into_matcher::FORMAT_PARAM;
+pub(crate) use self::into_matcher::compile_prefix;
+pub use self::scope::Scope;
+pub use self::resource::{Resource, ResourceAction};
+pub use self::guard::Guard;
+pub use self::route_table::{RouteInfo, RouteConflict, ConflictKind, RouteTable, find_conflicts};
 
 pub mod http_router;
 pub mod router;
+pub mod route_table;
 mod matcher;
+mod scope;
+mod resource;
+mod guard;
 mod into_matcher;
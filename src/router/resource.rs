@@ -0,0 +1,270 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use hyper::{Method, StatusCode};
+use hyper::header::{self, HeaderValue};
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::request::Request;
+use crate::response::Response;
+
+/// A controller for the conventional RESTful routes of a resource, see
+/// `HttpRouter::resource`.
+///
+/// Only the routes of the actions listed by `actions` are registered, other
+/// requests for the paths of the resource get `405 Method Not Allowed` and
+/// an `Allow` header listing the methods which are served there. The
+/// actions taking a single item find its id in the `id` param.
+///
+/// # Examples
+/// ```{rust}
+/// use async_trait::async_trait;
+/// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult, Resource, ResourceAction};
+///
+/// struct Users;
+///
+/// #[async_trait]
+/// impl Resource for Users {
+///     fn actions(&self) -> &'static [ResourceAction] {
+///         &[ResourceAction::Index, ResourceAction::Show]
+///     }
+///
+///     async fn index(&self, _req: &mut Request, res: Response) -> MiddlewareResult {
+///         res.send("all users")
+///     }
+///
+///     async fn show(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+///         let body = format!("user {}", req.param("id").unwrap());
+///         res.send(body)
+///     }
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.resource("/users", Users);
+/// ```
+#[async_trait]
+pub trait Resource<D: Send + 'static + Sync = ()>: Send + Sync + 'static {
+    /// The actions the resource implements. Which methods a type overrides
+    /// can't be told, so they have to be listed.
+    fn actions(&self) -> &'static [ResourceAction];
+
+    /// `GET /path`
+    async fn index(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        res.error(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED)
+    }
+
+    /// `GET /path/:id`
+    async fn show(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        res.error(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED)
+    }
+
+    /// `POST /path`
+    async fn create(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        res.error(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED)
+    }
+
+    /// `PUT /path/:id` and `PATCH /path/:id`
+    async fn update(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        res.error(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED)
+    }
+
+    /// `DELETE /path/:id`
+    async fn destroy(&self, _req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        res.error(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED)
+    }
+}
+
+// The error of the default actions, which are only reached for actions
+// which are listed but not implemented.
+const NOT_IMPLEMENTED: &str = "Resource action listed but not implemented";
+
+/// An action of a `Resource`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceAction {
+    Index,
+    Show,
+    Create,
+    Update,
+    Destroy,
+}
+
+// In the order of their routes.
+const ACTIONS: [ResourceAction; 5] = [ResourceAction::Index, ResourceAction::Create, ResourceAction::Show,
+                                      ResourceAction::Update, ResourceAction::Destroy];
+
+// The methods which get `405 Method Not Allowed` at the paths of a resource
+// if none of its actions serves them.
+const METHODS: [Method; 5] = [Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE];
+
+impl ResourceAction {
+    fn methods(self) -> &'static [Method] {
+        match self {
+            ResourceAction::Index | ResourceAction::Show => &[Method::GET],
+            ResourceAction::Create => &[Method::POST],
+            ResourceAction::Update => &[Method::PUT, Method::PATCH],
+            ResourceAction::Destroy => &[Method::DELETE]
+        }
+    }
+
+    // Whether the action is served at the path of a member rather than at
+    // that of the collection.
+    fn on_member(self) -> bool {
+        !matches!(self, ResourceAction::Index | ResourceAction::Create)
+    }
+}
+
+// The routes at the collection, or the member, path of a resource with
+// `actions`: the methods of the actions, and those which aren't allowed.
+pub(super) fn routes(actions: &[ResourceAction], on_member: bool)
+                     -> (Vec<(Method, ResourceAction)>, Vec<Method>, HeaderValue) {
+    let served: Vec<_> = ACTIONS.iter()
+        .filter(|action| action.on_member() == on_member && actions.contains(action))
+        .flat_map(|&action| action.methods().iter().map(move |method| (method.clone(), action)))
+        .collect();
+    let not_allowed = METHODS.iter()
+        .filter(|&method| !served.iter().any(|(served, _)| served == method))
+        .cloned()
+        .collect();
+    let allow: Vec<&str> = served.iter().map(|(method, _)| method.as_str()).collect();
+    let allow = HeaderValue::from_str(&allow.join(", ")).unwrap();
+    (served, not_allowed, allow)
+}
+
+// The route handler for one action of a resource.
+pub(super) struct ResourceHandler<R> {
+    pub resource: Arc<R>,
+    pub action: ResourceAction,
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync, R: Resource<D>> Middleware<D> for ResourceHandler<R> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        match self.action {
+            ResourceAction::Index => self.resource.index(req, res).await,
+            ResourceAction::Show => self.resource.show(req, res).await,
+            ResourceAction::Create => self.resource.create(req, res).await,
+            ResourceAction::Update => self.resource.update(req, res).await,
+            ResourceAction::Destroy => self.resource.destroy(req, res).await
+        }
+    }
+}
+
+// The route handler for the methods a resource doesn't serve at a path.
+pub(super) struct NotAllowed {
+    pub allow: HeaderValue,
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for NotAllowed {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        res.set_header(header::ALLOW, self.allow.clone());
+        let message = format!("{} is not allowed for {}", req.origin.method(), req.path_without_query());
+        res.error(StatusCode::METHOD_NOT_ALLOWED, message)
+    }
+}
+
+#[tokio::test]
+async fn resource_routes() {
+    use hyper::Method;
+    use crate::router::{Guard, HttpRouter, Router};
+    use crate::test_util::run;
+
+    struct Users;
+
+    #[async_trait]
+    impl Resource for Users {
+        fn actions(&self) -> &'static [ResourceAction] {
+            &[ResourceAction::Index, ResourceAction::Show, ResourceAction::Update]
+        }
+
+        async fn index(&self, _req: &mut Request, res: Response) -> MiddlewareResult {
+            res.send("users")
+        }
+
+        async fn show(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            let body = format!("user {}", req.param("id").unwrap());
+            res.send(body)
+        }
+
+        async fn update(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            let body = format!("{} user {}", req.origin.method(), req.param("id").unwrap());
+            res.send(body)
+        }
+    }
+
+    struct Posts;
+
+    #[async_trait]
+    impl Resource for Posts {
+        fn actions(&self) -> &'static [ResourceAction] {
+            &[ResourceAction::Show]
+        }
+
+        async fn show(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            let body = format!("post {} of user {}", req.param("id").unwrap(), req.param("user_id").unwrap());
+            res.send(body)
+        }
+    }
+
+    struct Comments;
+
+    #[async_trait]
+    impl Resource for Comments {
+        fn actions(&self) -> &'static [ResourceAction] {
+            &[ResourceAction::Destroy, ResourceAction::Index]
+        }
+
+        async fn index(&self, _req: &mut Request, res: Response) -> MiddlewareResult {
+            res.send("comments")
+        }
+
+        async fn destroy(&self, _req: &mut Request, res: Response) -> MiddlewareResult {
+            res.send("deleted")
+        }
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.resource("/comments", Comments);
+        router.resource("/users", Users)
+              .scope("/users/:user_id", |user| { user.resource("/posts", Posts); });
+        router.resource("/admin/users", Users).guard(Guard::host("admin.example.com"));
+        router
+    }
+
+    assert_eq!(run(router(), Method::GET, "/users").await, (StatusCode::OK, "users".to_string()));
+    assert_eq!(run(router(), Method::GET, "/users/7").await.1, "user 7");
+    assert_eq!(run(router(), Method::PATCH, "/users/7").await.1, "PATCH user 7");
+    assert_eq!(run(router(), Method::PUT, "/users/7").await.1, "PUT user 7");
+    assert_eq!(run(router(), Method::DELETE, "/comments/1").await.1, "deleted");
+    assert_eq!(run(router(), Method::GET, "/users/7/posts/3").await.1, "post 3 of user 7");
+
+    // the guard applies to every route of the resource
+    assert_eq!(run(router(), Method::GET, "/admin/users").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(run(router(), Method::GET, "/admin/users/7").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(run(router(), Method::POST, "/admin/users").await.0, StatusCode::NOT_FOUND);
+
+    // the others get `405 Method Not Allowed`, with the methods served at the
+    // path
+    let router = &router();
+    let allow = |method: Method, path: &'static str| {
+        let mut req = crate::test_util::request(method, path, Arc::new(()));
+        let res = crate::test_util::response(Arc::new(()));
+        async move {
+            let err = match router.invoke(&mut req, res).await {
+                Err(err) => err,
+                Ok(_) => panic!("expected a 405 error")
+            };
+            let res = err.stream.unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            res.headers()[header::ALLOW].to_str().unwrap().to_string()
+        }
+    };
+    assert_eq!(allow(Method::POST, "/users").await, "GET");
+    assert_eq!(allow(Method::PUT, "/users").await, "GET");
+    assert_eq!(allow(Method::DELETE, "/users").await, "GET");
+    assert_eq!(allow(Method::DELETE, "/users/7").await, "GET, PUT, PATCH");
+    assert_eq!(allow(Method::POST, "/users/7").await, "GET, PUT, PATCH");
+    assert_eq!(allow(Method::POST, "/comments").await, "GET");
+    assert_eq!(allow(Method::GET, "/comments/1").await, "DELETE");
+    assert_eq!(allow(Method::GET, "/users/7/posts").await, "");
+    assert_eq!(allow(Method::DELETE, "/users/7/posts/3").await, "GET");
+}
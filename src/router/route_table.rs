@@ -0,0 +1,319 @@
+//! Introspection of the routes registered in a `MiddlewareStack`
+use std::fmt;
+use async_trait::async_trait;
+use hyper::Method;
+use serde_json::{self, json};
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::into_matcher::FORMAT_SUFFIX;
+
+/// Describes a single registered route, as returned by `Nickel::routes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteInfo {
+    /// The method the route responds to.
+    pub method: Method,
+    /// The pattern (or regex) the route was registered with, as seen by the
+    /// router after any mount point has been stripped.
+    pub pattern: String,
+    /// The name given with `Router::name`, if any.
+    pub name: Option<String>,
+    /// The mount point(s) the route's router is mounted under, if any.
+    pub mount_prefix: Option<String>,
+    /// Descriptions of the route's guards, see `Guard`.
+    pub guards: Vec<String>,
+    // Whether `pattern` is a route pattern, regexes are only compared for
+    // exact duplicates.
+    is_pattern: bool,
+}
+
+impl RouteInfo {
+    pub(crate) fn new(method: Method, pattern: &str, name: Option<String>, is_pattern: bool) -> RouteInfo {
+        RouteInfo {
+            method,
+            pattern: pattern.to_string(),
+            name,
+            mount_prefix: None,
+            guards: Vec::new(),
+            is_pattern
+        }
+    }
+
+    pub(crate) fn with_guards(mut self, guards: Vec<String>) -> RouteInfo {
+        self.guards = guards;
+        self
+    }
+
+    /// Nests the route under `prefix`, used by `Mount` to report its routes.
+    pub fn with_prefix(mut self, prefix: &str) -> RouteInfo {
+        self.mount_prefix = Some(match self.mount_prefix.take() {
+            Some(inner) => format!("{}{}", prefix.trim_end_matches('/'), inner),
+            None => prefix.to_string()
+        });
+        self
+    }
+
+    /// The full pattern, including the mount prefix, a request path has to
+    /// match to reach the route.
+    pub fn full_pattern(&self) -> String {
+        match self.mount_prefix {
+            Some(ref prefix) => join_path(prefix, &self.pattern),
+            None => self.pattern.clone()
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "method": self.method.as_str(),
+            "pattern": self.pattern,
+            "name": self.name,
+            "mount_prefix": self.mount_prefix,
+            "guards": self.guards,
+        })
+    }
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.full_pattern())?;
+        if let Some(ref name) = self.name {
+            write!(f, " ({})", name)?;
+        }
+        if !self.guards.is_empty() {
+            write!(f, " [{}]", self.guards.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both routes have the same method and pattern.
+    Duplicate,
+    /// Every path matching the later route is matched by the earlier one.
+    Shadowed,
+}
+
+/// A route which can never be reached because an earlier route takes all of
+/// its requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteConflict {
+    pub kind: ConflictKind,
+    /// The unreachable route.
+    pub route: RouteInfo,
+    /// The earlier route which takes its requests.
+    pub shadowed_by: RouteInfo,
+}
+
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ConflictKind::Duplicate => write!(f, "Route {} is a duplicate of {}", self.route, self.shadowed_by),
+            ConflictKind::Shadowed => write!(f, "Route {} is shadowed by {}", self.route, self.shadowed_by)
+        }
+    }
+}
+
+/// Finds routes which can never match because a route earlier in `routes`
+/// (i.e. earlier in the middleware stack) takes all of their requests.
+///
+/// This works on the structure of route patterns, so it is conservative: a
+/// conflict is only reported if it is certain. Routes registered with a regex
+/// are only checked for exact duplicates, and routes with guards never take
+/// all requests of another route.
+pub fn find_conflicts(routes: &[RouteInfo]) -> Vec<RouteConflict> {
+    let mut conflicts = Vec::new();
+
+    for (i, later) in routes.iter().enumerate() {
+        let later_pattern = later.full_pattern();
+        let earlier = routes[..i].iter().filter(|r| r.method == later.method && r.guards.is_empty());
+
+        for route in earlier {
+            let pattern = route.full_pattern();
+            let kind = if pattern == later_pattern && route.is_pattern == later.is_pattern {
+                ConflictKind::Duplicate
+            } else if route.is_pattern && later.is_pattern && covers(&pattern, &later_pattern) {
+                ConflictKind::Shadowed
+            } else {
+                continue
+            };
+
+            conflicts.push(RouteConflict {
+                kind,
+                route: later.clone(),
+                shadowed_by: route.clone()
+            });
+            break;
+        }
+    }
+
+    conflicts
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    // `:param` or `*`
+    Any,
+    // `**` or `*name`
+    Many,
+    // Anything mixing literal text with params or regex syntax
+    Opaque(&'a str),
+}
+
+fn segments(pattern: &str) -> Vec<Segment<'_>> {
+    let pattern = pattern.strip_suffix(FORMAT_SUFFIX).unwrap_or(pattern);
+
+    pattern.split('/').map(|s| {
+        let is_name = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if s == "*" || (s.starts_with(':') && s[1..].chars().all(|c| c.is_alphanumeric() || ",_-".contains(c))) {
+            Segment::Any
+        } else if s == "**" || (s.starts_with('*') && is_name(s.trim_start_matches('*'))) {
+            Segment::Many
+        } else if s.contains(|c| ":*()[]|?+\\".contains(c)) {
+            Segment::Opaque(s)
+        } else {
+            Segment::Literal(s)
+        }
+    }).collect()
+}
+
+// Whether every path matched by pattern `b` is also matched by pattern `a`.
+fn covers(a: &str, b: &str) -> bool {
+    covers_segments(&segments(a), &segments(b))
+}
+
+fn covers_segments(a: &[Segment<'_>], b: &[Segment<'_>]) -> bool {
+    use self::Segment::*;
+
+    match (a.first(), b.first()) {
+        (None, None) => true,
+        (Some(Many), _) => (1..=b.len()).any(|i| covers_segments(&a[1..], &b[i..])),
+        (_, Some(Many)) | (None, _) | (_, None) => false,
+        (Some(Any), Some(Opaque(s))) if s.contains('*') => false,
+        (Some(Any), Some(_)) => covers_segments(&a[1..], &b[1..]),
+        (Some(Literal(x)), Some(Literal(y))) |
+        (Some(Opaque(x)), Some(Opaque(y))) if x == y => covers_segments(&a[1..], &b[1..]),
+        _ => false
+    }
+}
+
+fn join_path(prefix: &str, path: &str) -> String {
+    format!("{}/{}", prefix.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Serves the route table as JSON, see `Options::route_table`.
+pub struct RouteTable {
+    path: String,
+    json: String,
+}
+
+impl RouteTable {
+    pub fn new<S: Into<String>>(path: S, routes: &[RouteInfo]) -> RouteTable {
+        let routes: Vec<_> = routes.iter().map(RouteInfo::to_json).collect();
+        RouteTable {
+            path: path.into(),
+            json: serde_json::Value::Array(routes).to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for RouteTable {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        if req.origin.method() == Method::GET && req.path_without_query() == self.path {
+            res.set(MediaType::Json);
+            res.send(self.json.clone())
+        } else {
+            res.next_middleware()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(method: Method, pattern: &str) -> RouteInfo {
+        RouteInfo::new(method, &format!("{}{}", pattern, FORMAT_SUFFIX), None, true)
+    }
+
+    #[test]
+    fn finds_duplicates() {
+        let routes = vec![route(Method::GET, "/users"),
+                          route(Method::POST, "/users"),
+                          route(Method::GET, "/users")];
+        let conflicts = find_conflicts(&routes);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::Duplicate);
+        assert_eq!(conflicts[0].route, routes[2]);
+        assert_eq!(conflicts[0].shadowed_by, routes[0]);
+    }
+
+    #[test]
+    fn finds_shadowed_routes() {
+        let routes = vec![route(Method::GET, "/users/:id"),
+                          route(Method::GET, "/users/new"),
+                          route(Method::GET, "/files/**"),
+                          route(Method::GET, "/files/a/*/c"),
+                          route(Method::GET, "/files/*path"),
+                          route(Method::GET, "/users/:id/posts"),
+                          route(Method::GET, "/users/*/posts")];
+        let conflicts = find_conflicts(&routes);
+        let shadowed: Vec<_> = conflicts.iter().map(|c| (c.kind, &*c.route.pattern, &*c.shadowed_by.pattern)).collect();
+
+        assert_eq!(shadowed, vec![
+            (ConflictKind::Shadowed, routes[1].pattern.as_str(), routes[0].pattern.as_str()),
+            (ConflictKind::Shadowed, routes[3].pattern.as_str(), routes[2].pattern.as_str()),
+            (ConflictKind::Shadowed, routes[4].pattern.as_str(), routes[2].pattern.as_str()),
+            (ConflictKind::Shadowed, routes[6].pattern.as_str(), routes[5].pattern.as_str()),
+        ]);
+    }
+
+    #[test]
+    fn ignores_reachable_routes() {
+        let routes = vec![route(Method::GET, "/users/new"),
+                          route(Method::GET, "/users/:id"),
+                          route(Method::GET, "/users/*"),
+                          route(Method::GET, "/files/:name"),
+                          route(Method::GET, "/files/**"),
+                          route(Method::GET, "/a/:id-:rev"),
+                          route(Method::GET, "/a/*/b"),
+                          RouteInfo::new(Method::GET, "/(foo|bar)", None, false),
+                          route(Method::GET, "/foo")];
+
+        // only the wildcard after `:id` is unreachable
+        let conflicts = find_conflicts(&routes);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].route, routes[2]);
+    }
+
+    #[test]
+    fn guarded_routes_do_not_shadow() {
+        let routes = vec![route(Method::POST, "/upload").with_guards(vec!["content type application/json".to_string()]),
+                          route(Method::POST, "/upload"),
+                          route(Method::POST, "/upload")];
+        let conflicts = find_conflicts(&routes);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].route, routes[2]);
+        assert_eq!(conflicts[0].shadowed_by, routes[1]);
+        assert_eq!(routes[0].to_string(), format!("POST /upload{} [content type application/json]", FORMAT_SUFFIX));
+    }
+
+    #[test]
+    fn compares_mounted_routes_with_prefix() {
+        let routes = vec![route(Method::GET, "/api/**"),
+                          route(Method::GET, "/users").with_prefix("/api/"),
+                          route(Method::GET, "/users").with_prefix("/admin/")];
+        let conflicts = find_conflicts(&routes);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].route.full_pattern(), format!("/api/users{}", FORMAT_SUFFIX));
+
+        let nested = route(Method::GET, "/users").with_prefix("/v1/").with_prefix("/api/");
+        assert_eq!(nested.mount_prefix.as_ref().map(|s| &s[..]), Some("/api/v1/"));
+    }
+}
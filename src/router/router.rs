@@ -1,11 +1,12 @@
-use crate::middleware::{Middleware, MiddlewareResult};
+use crate::middleware::{Middleware, MiddlewareResult, ErrorHandler, LocalHandlers};
 
 use async_trait::async_trait;
 use crate::request::Request;
 use crate::response::Response;
 use crate::router::HttpRouter;
 use hyper::{Method, StatusCode};
-use crate::router::{Matcher, FORMAT_PARAM};
+use regex::Regex;
+use crate::router::{Guard, Matcher, RouteInfo, Scope, FORMAT_PARAM};
 
 /// A Route is the basic data structure that stores both the path
 /// and the handler that gets executed for the route.
@@ -13,7 +14,19 @@ use crate::router::{Matcher, FORMAT_PARAM};
 pub struct Route<D=()> {
     pub method: Method,
     pub handler: Box<dyn Middleware<D> + Send + Sync + 'static>,
-    matcher: Matcher
+    pub name: Option<String>,
+    pub(crate) matcher: Matcher,
+    pub(crate) guards: Vec<Guard<D>>
+}
+
+impl<D> Route<D> {
+    // Whether the route matches `req` at `path`, which is the request path
+    // as seen by the router.
+    fn matches(&self, req: &Request<D>, path: &str) -> bool {
+        self.method == *req.origin.method()
+            && self.matcher.regex(req.ignore_case).is_match(path)
+            && self.guards.iter().all(|guard| guard.check(req))
+    }
 }
 
 /// A RouteResult is what the router returns when `match_route` is called.
@@ -42,34 +55,183 @@ impl RouteResult {
     }
 }
 
+// The not found handler of a scope, for requests under its prefix
+pub(crate) struct Fallback<D> {
+    pub matcher: Matcher,
+    pub handler: Box<dyn Middleware<D> + Send + Sync + 'static>
+}
+
 /// The Router's job is it to hold routes and to resolve them later against
 /// concrete URLs. The router is also a regular middleware and needs to be
 /// added to the middleware stack with `server.utilize(router)`.
 pub struct Router<D=()> {
     routes: Vec<Route<D>>,
+    fallbacks: Vec<Fallback<D>>,
+    handlers: LocalHandlers<D>,
+    // How many of the last routes `name` and `guard` apply to.
+    grouped: usize,
 }
 
 impl<D> Router<D> {
     pub fn new() -> Router<D> {
         Router {
-            routes: Vec::new()
+            routes: Vec::new(),
+            fallbacks: Vec::new(),
+            handlers: LocalHandlers::new(),
+            grouped: 0
+        }
+    }
+
+    /// Names the most recently added route. The name is shown in the route
+    /// table, see `Nickel::routes`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    ///
+    /// fn main() {
+    ///     let mut router = Nickel::router();
+    ///     router.get("/users/:id", middleware! { "a user" }).name("user");
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if no route has been added yet, or if the most recently added
+    /// routes are those of a resource.
+    pub fn name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        assert!(self.grouped <= 1, "Router::name can't name the routes of a resource");
+        let route = self.routes.last_mut().expect("Router::name called before adding a route");
+        route.name = Some(name.into());
+        self
+    }
+
+    /// Adds a guard to the most recently added route, or to all routes of
+    /// the most recently added resource, see `Guard`.
+    ///
+    /// # Panics
+    /// Panics if no route has been added yet.
+    pub fn guard(&mut self, guard: Guard<D>) -> &mut Self {
+        let start = self.routes.len().checked_sub(self.grouped)
+            .filter(|_| self.grouped > 0)
+            .expect("Router::guard called before adding a route");
+        for route in &mut self.routes[start..] {
+            route.guards.push(guard.clone());
         }
+        self
+    }
+
+    /// Groups routes under a common path prefix, with middleware which only
+    /// runs when one of the grouped routes matches. Scopes can be nested and
+    /// their prefixes may contain params and wildcards.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    ///
+    /// fn main() {
+    ///     let mut router = Nickel::router();
+    ///
+    ///     router.scope("/admin", |admin| {
+    ///         admin.utilize(middleware! { |request|
+    ///             println!("admin access: {}", request.origin.uri());
+    ///         });
+    ///         admin.get("/users", middleware! { "all users" });
+    ///
+    ///         admin.scope("/orgs/:org", |org| {
+    ///             org.get("/members", middleware! { |request|
+    ///                 format!("members of {}", request.param("org").unwrap())
+    ///             });
+    ///         });
+    ///     });
+    /// }
+    /// ```
+    pub fn scope<S, F>(&mut self, prefix: S, f: F) -> &mut Self
+    where S: Into<String>, F: FnOnce(&mut Scope<D>), D: Send + 'static + Sync {
+        let mut scope = Scope::new(prefix);
+        f(&mut scope);
+        let (routes, fallbacks) = scope.into_parts();
+        self.routes.extend(routes);
+        self.fallbacks.extend(fallbacks);
+        self.grouped = usize::from(!self.routes.is_empty());
+        self
     }
 
+    /// Registers a handler for requests which match none of the routes. The
+    /// response status is already set to `404 Not Found` when it is invoked.
+    ///
+    /// Without one, such requests are passed on to the next middleware. Note
+    /// that with one, no middleware after the router sees unmatched requests,
+    /// so this is mostly useful for mounted routers and scopes.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, Mountable, MediaType};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     let mut api = Nickel::router();
+    ///
+    ///     api.get("/users", middleware! { r#"["alice", "bob"]"# });
+    ///     api.not_found(middleware! { |_, mut res|
+    ///         res.set(MediaType::Json);
+    ///         r#"{"error": "no such endpoint"}"#
+    ///     });
+    ///
+    ///     server.mount("/api/", api).unwrap();
+    /// }
+    /// ```
+    pub fn not_found<T: Middleware<D>>(&mut self, handler: T) -> &mut Self
+    where D: Send + 'static + Sync {
+        self.handlers.set_not_found(handler);
+        self
+    }
+
+    /// Registers an error handler for errors returned by this router's
+    /// routes and its not found handler. Like with `Nickel::handle_error`,
+    /// the last registered handler is tried first. Errors which none of them
+    /// handle are passed on to the global error handlers.
+    pub fn handle_error<T: ErrorHandler<D>>(&mut self, handler: T) -> &mut Self
+    where D: Send + 'static + Sync {
+        self.handlers.add_error_handler(handler);
+        self
+    }
+
+    /// Finds the route for `method` and `path`, without checking guards.
     pub fn match_route(&self, method: &Method, path: &str) -> Option<(RouteResult, &Route<D>)> {
         self.routes
             .iter()
             .find(|item| item.method == *method && item.matcher.is_match(path))
-            .map(|route| (RouteResult{params: extract_params(route, path)}, route))
+            .map(|route| (RouteResult{params: extract_params(&route.matcher, path)}, route))
+    }
+
+    // Like `match_route`, but for the request as a whole. Paths are matched
+    // without case if the request asks for it, see `NormalizePath`.
+    fn match_request(&self, req: &Request<D>) -> Option<(RouteResult, &Route<D>)> {
+        let path = req.path_without_query();
+        self.routes
+            .iter()
+            .find(|route| route.matches(req, path))
+            .map(|route| (RouteResult{params: extract_params(route.matcher.regex(req.ignore_case), path)}, route))
+    }
+
+    fn match_fallback(&self, req: &Request<D>) -> Option<(RouteResult, &Fallback<D>)> {
+        let path = req.path_without_query();
+        self.fallbacks
+            .iter()
+            .find(|item| item.matcher.regex(req.ignore_case).is_match(path))
+            .map(|fallback| (RouteResult{params: extract_params(fallback.matcher.regex(req.ignore_case), path)}, fallback))
     }
 }
 
-fn extract_params<D>(route: &Route<D>, path: &str) -> Vec<(String, String)> {
-    let captures = match route.matcher.captures(path) {
+fn extract_params(matcher: &Regex, path: &str) -> Vec<(String, String)> {
+    let captures = match matcher.captures(path) {
         Some(cap) => cap,
         None => { return vec![]; },
     };
-    route.matcher.capture_names()
+    matcher.capture_names()
         .filter_map(|n| {
             let name = if let Some(name) = n {
                 name
@@ -92,9 +254,17 @@ impl<D: Send + 'static + Sync> HttpRouter<D> for Router<D> {
             matcher: matcher.into(),
             method: method,
             handler: Box::new(handler),
+            name: None,
+            guards: Vec::new(),
         };
 
         self.routes.push(route);
+        self.grouped = 1;
+        self
+    }
+
+    fn group(&mut self, count: usize) -> &mut Self {
+        self.grouped = count;
         self
     }
 }
@@ -105,19 +275,34 @@ impl<D: Send + Sync + 'static> Middleware<D> for Router<D> {
                           -> MiddlewareResult<D> {
         debug!("Router::invoke for '{:?}'", req.origin.uri());
 
-        // Strip off the querystring when matching a route
-        let route_result = self.match_route(&req.origin.method(), req.path_without_query());
+        let route_result = self.match_request(req);
 
         debug!("route_result.route.path: {:?}", route_result.as_ref().map(|(_, r)| r.matcher.path()));
 
-        match route_result {
+        let result = match route_result {
             Some((route_result, route)) => {
                 res.set(StatusCode::OK);
                 req.route_result = Some(route_result);
                 route.handler.invoke(req, res).await
             },
-            None => res.next_middleware()
-        }
+            None => match self.match_fallback(req) {
+                Some((route_result, fallback)) => {
+                    req.route_result = Some(route_result);
+                    fallback.handler.invoke(req, res).await
+                },
+                None => self.handlers.not_found(req, res).await
+            }
+        };
+        self.handlers.handle_errors(result, req)
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.routes.iter().map(|route| {
+            let guards = route.guards.iter().map(|g| g.description().to_string()).collect();
+            RouteInfo::new(route.method.clone(), route.matcher.path(),
+                           route.name.clone(), route.matcher.is_pattern())
+                .with_guards(guards)
+        }).collect()
     }
 }
 
@@ -196,62 +381,62 @@ fn creates_valid_regex_for_routes () {
 fn can_match_var_routes () {
     let route_store = &mut Router::<()>::new();
 
-    route_store.add_route(Method::Get, "/foo/:userid", middleware! { "hello from foo" });
-    route_store.add_route(Method::Get, "/bar", middleware! { "hello from foo" });
-    route_store.add_route(Method::Get, "/file/:format/:file", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/foo/:userid", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/bar", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/file/:format/:file", middleware! { "hello from foo" });
 
-    let route_result = route_store.match_route(&Method::Get, "/foo/4711").unwrap();
+    let route_result = route_store.match_route(&Method::GET, "/foo/4711").unwrap().0;
     assert_eq!(route_result.param("userid"), Some("4711"));
 
-    let route_result = route_store.match_route(&Method::Get, "/bar/4711");
+    let route_result = route_store.match_route(&Method::GET, "/bar/4711");
     assert!(route_result.is_none());
 
-    let route_result = route_store.match_route(&Method::Get, "/foo");
+    let route_result = route_store.match_route(&Method::GET, "/foo");
     assert!(route_result.is_none());
 
     // ensure that this will work with commas too
-    let route_result = route_store.match_route(&Method::Get, "/foo/123,456");
+    let route_result = route_store.match_route(&Method::GET, "/foo/123,456");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("userid"), Some("123,456"));
 
     // ensure that this will work with spacing too
-    let route_result = route_store.match_route(&Method::Get, "/foo/John%20Doe");
+    let route_result = route_store.match_route(&Method::GET, "/foo/John%20Doe");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("userid"), Some("John%20Doe"));
 
     // check for optional format param
-    let route_result = route_store.match_route(&Method::Get, "/foo/John%20Doe.json");
+    let route_result = route_store.match_route(&Method::GET, "/foo/John%20Doe.json");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("userid"), Some("John%20Doe"));
     assert_eq!(route_result.param("format"), Some("json"));
 
     // ensure format works with queries
-    let route_result = route_store.match_route(&Method::Get,
+    let route_result = route_store.match_route(&Method::GET,
     "/foo/5490,1234.csv?foo=true&bar=false");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     // NOTE: `.param` doesn't cover query params currently
     assert_eq!(route_result.param("userid"), Some("5490,1234"));
     assert_eq!(route_result.param("format"), Some("csv"));
 
     // ensure format works with no format
-    let route_result = route_store.match_route(&Method::Get,
-                                               "/foo/5490,1234?foo=true&bar=false").unwrap();
+    let route_result = route_store.match_route(&Method::GET,
+                                               "/foo/5490,1234?foo=true&bar=false").unwrap().0;
 
     assert_eq!(route_result.param("format"), Some(""));
 
     // ensure format works if defined by user
-    let route_result = route_store.match_route(&Method::Get, "/file/markdown/something?foo=true");
+    let route_result = route_store.match_route(&Method::GET, "/file/markdown/something?foo=true");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     // NOTE: `.param` doesn't cover query params currently
     assert_eq!(route_result.param("file"), Some("something"));
     assert_eq!(route_result.param("format"), Some("markdown"));
@@ -262,13 +447,13 @@ fn params_lifetime() {
     let route_store = &mut Router::<()>::new();
     let handler = middleware! { "hello from foo" };
 
-    route_store.add_route(Method::Get, "/file/:format/:file", handler);
+    route_store.add_route(Method::GET, "/file/:format/:file", handler);
 
-    let route_result = route_store.match_route(&Method::Get, "/file/txt/manual");
+    let route_result = route_store.match_route(&Method::GET, "/file/txt/manual");
     assert!(route_result.is_some());
 
     // Ensure two params can live without borrowck problems
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     let format = route_result.param("format");
     let file = route_result.param("file");
     assert_eq!(format, Some("txt"));
@@ -282,18 +467,18 @@ fn regex_path() {
     let route_store = &mut Router::<()>::new();
 
     let regex = Regex::new("/(foo|bar)").unwrap();
-    route_store.add_route(Method::Get, regex, middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, regex, middleware! { "hello from foo" });
 
-    let route_result = route_store.match_route(&Method::Get, "/foo");
+    let route_result = route_store.match_route(&Method::GET, "/foo");
     assert!(route_result.is_some());
 
-    let route_result = route_store.match_route(&Method::Get, "/bar");
+    let route_result = route_store.match_route(&Method::GET, "/bar");
     assert!(route_result.is_some());
 
-    let route_result = route_store.match_route(&Method::Get, "/bar?foo");
+    let route_result = route_store.match_route(&Method::GET, "/bar?foo");
     assert!(route_result.is_some());
 
-    let route_result = route_store.match_route(&Method::Get, "/baz");
+    let route_result = route_store.match_route(&Method::GET, "/baz");
     assert!(route_result.is_none());
 }
 
@@ -304,21 +489,21 @@ fn regex_path_named() {
     let route_store = &mut Router::<()>::new();
 
     let regex = Regex::new("/(?P<a>foo|bar)/b").unwrap();
-    route_store.add_route(Method::Get, regex, middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, regex, middleware! { "hello from foo" });
 
-    let route_result = route_store.match_route(&Method::Get, "/foo/b");
+    let route_result = route_store.match_route(&Method::GET, "/foo/b");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("a"), Some("foo"));
 
-    let route_result = route_store.match_route(&Method::Get, "/bar/b");
+    let route_result = route_store.match_route(&Method::GET, "/bar/b");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("a"), Some("bar"));
 
-    let route_result = route_store.match_route(&Method::Get, "/baz/b");
+    let route_result = route_store.match_route(&Method::GET, "/baz/b");
     assert!(route_result.is_none());
 }
 
@@ -329,19 +514,236 @@ fn ignores_querystring() {
     let route_store = &mut Router::<()>::new();
 
     let regex = Regex::new("/(?P<a>foo|bar)/b").unwrap();
-    route_store.add_route(Method::Get, regex, middleware! { "hello from foo" });
-    route_store.add_route(Method::Get, "/:foo", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, regex, middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/:foo", middleware! { "hello from foo" });
 
     // Should ignore the querystring
-    let route_result = route_store.match_route(&Method::Get, "/moo?foo");
+    let route_result = route_store.match_route(&Method::GET, "/moo?foo");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("foo"), Some("moo"));
 
-    let route_result = route_store.match_route(&Method::Get, "/bar/b?foo");
+    let route_result = route_store.match_route(&Method::GET, "/bar/b?foo");
     assert!(route_result.is_some());
 
-    let route_result = route_result.unwrap();
+    let route_result = route_result.unwrap().0;
     assert_eq!(route_result.param("a"), Some("bar"));
 }
+
+#[test]
+fn matches_rfc3986_segment_characters() {
+    let route_store = &mut Router::<()>::new();
+
+    route_store.add_route(Method::GET, "/users/:name", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/any/*/end", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/dotted/:name/profile", middleware! { "hello from foo" });
+
+    for name in &["~jane", "jane@home", "a:b", "it's(1)+2;x=y!$",
+                  "caf%C3%A9", "café"] {
+        let route_result = route_store.match_route(&Method::GET, &format!("/users/{}", name));
+        assert!(route_result.is_some(), "expected {:?} to match", name);
+        assert_eq!(route_result.unwrap().0.param("name"), Some(*name));
+    }
+
+    let route_result = route_store.match_route(&Method::GET, "/dotted/jane.doe/profile").unwrap().0;
+    assert_eq!(route_result.param("name"), Some("jane.doe"));
+
+    assert!(route_store.match_route(&Method::GET, "/any/a.b~c@d:e/end").is_some());
+    assert!(route_store.match_route(&Method::GET, "/any/a/b/end").is_none());
+
+    // the format is taken from the last extension only
+    let route_result = route_store.match_route(&Method::GET, "/users/jane.doe.json").unwrap().0;
+    assert_eq!(route_result.param("name"), Some("jane.doe"));
+    assert_eq!(route_result.param("format"), Some("json"));
+}
+
+#[test]
+fn captures_named_tails() {
+    let route_store = &mut Router::<()>::new();
+
+    route_store.add_route(Method::GET, "/files/*path", middleware! { "hello from foo" });
+    route_store.add_route(Method::GET, "/archive/*rest/download", middleware! { "hello from foo" });
+
+    let route_result = route_store.match_route(&Method::GET, "/files/docs/2020/report.pdf").unwrap().0;
+    assert_eq!(route_result.param("path"), Some("docs/2020/report.pdf"));
+    assert_eq!(route_result.param("format"), Some("pdf"));
+
+    let route_result = route_store.match_route(&Method::GET, "/files/docs/README?raw=1").unwrap().0;
+    assert_eq!(route_result.param("path"), Some("docs/README"));
+    assert_eq!(route_result.param("format"), Some(""));
+
+    let route_result = route_store.match_route(&Method::GET, "/archive/a/b.c/download").unwrap().0;
+    assert_eq!(route_result.param("rest"), Some("a/b.c"));
+
+    let route_result = route_store.match_route(&Method::GET, "/archive/a/b/download.zip").unwrap().0;
+    assert_eq!(route_result.param("rest"), Some("a/b"));
+    assert_eq!(route_result.param("format"), Some("zip"));
+}
+
+#[tokio::test]
+async fn scopes_prefix_routes_and_run_their_middleware() {
+    use crate::test_util::run;
+
+    fn deny(_: &mut Request, mut res: Response) -> MiddlewareResult {
+        res.set(StatusCode::FORBIDDEN);
+        res.send("denied")
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.get("/public", middleware! { "public" });
+        router.scope("/admin", |admin| {
+            admin.utilize(deny);
+            admin.get("/users", middleware! { "users" });
+        });
+        router
+    }
+
+    assert_eq!(run(router(), Method::GET, "/public").await, (StatusCode::OK, "public".to_string()));
+    assert_eq!(run(router(), Method::GET, "/admin/users").await, (StatusCode::FORBIDDEN, "denied".to_string()));
+    // scope middleware doesn't run without a matching route
+    assert_eq!(run(router(), Method::GET, "/admin/other").await, (StatusCode::NOT_FOUND, "".to_string()));
+}
+
+#[tokio::test]
+async fn nested_scopes_merge_params() {
+    use crate::test_util::run;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.scope("/orgs/:org", |org| {
+            org.utilize(middleware! { |_, mut res|
+                res.set_header(hyper::header::CACHE_CONTROL, hyper::header::HeaderValue::from_static("no-cache"));
+            });
+            org.scope("/teams/:team/", |team| {
+                team.get("/members/:id", middleware! { |request|
+                    format!("{} {} {}", request.param("org").unwrap(),
+                            request.param("team").unwrap(), request.param("id").unwrap())
+                });
+            });
+        });
+        router
+    }
+
+    let route_result = router().match_route(&Method::GET, "/orgs/acme/teams/ops/members/7").unwrap().0;
+    assert_eq!(route_result.param("org"), Some("acme"));
+    assert_eq!(route_result.param("team"), Some("ops"));
+    assert_eq!(route_result.param("id"), Some("7"));
+    assert!(router().match_route(&Method::GET, "/teams/ops/members/7").is_none());
+
+    assert_eq!(run(router(), Method::GET, "/orgs/acme/teams/ops/members/7").await,
+               (StatusCode::OK, "acme ops 7".to_string()));
+}
+
+#[cfg(test)]
+fn json_errors(err: &mut crate::NickelError, _: &mut Request) -> crate::Action {
+    if let Some(ref mut res) = err.stream {
+        res.set_body(format!(r#"{{"error": "{}"}}"#, err.message));
+    }
+    crate::Halt(())
+}
+
+#[tokio::test]
+async fn router_not_found_and_error_handlers() {
+    use crate::test_util::run;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.get("/ok", middleware! { "ok" });
+        router.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad input") });
+        router.not_found(middleware! { "nothing here" });
+        let handler: fn(&mut crate::NickelError, &mut Request) -> crate::Action = json_errors;
+        router.handle_error(handler);
+        router
+    }
+
+    assert_eq!(run(router(), Method::GET, "/ok").await, (StatusCode::OK, "ok".to_string()));
+    assert_eq!(run(router(), Method::GET, "/nope").await, (StatusCode::NOT_FOUND, "nothing here".to_string()));
+    assert_eq!(run(router(), Method::GET, "/fail").await,
+               (StatusCode::BAD_REQUEST, r#"{"error": "bad input"}"#.to_string()));
+
+    // without local handlers the global ones apply
+    let mut router = Router::<()>::new();
+    router.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad input") });
+    assert_eq!(run(router, Method::GET, "/fail").await, (StatusCode::BAD_REQUEST, "Bad Request".to_string()));
+}
+
+#[tokio::test]
+async fn scope_not_found_and_error_handlers() {
+    use crate::test_util::run;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.scope("/api", |api| {
+            api.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad input") });
+            api.not_found(middleware! { r#"{"error": "no such endpoint"}"# });
+            let handler: fn(&mut crate::NickelError, &mut Request) -> crate::Action = json_errors;
+            api.handle_error(handler);
+
+            api.scope("/v2/:team", |v2| {
+                v2.get("/users", middleware! { "users" });
+                v2.not_found(middleware! { |request|
+                    format!("{} has no such endpoint", request.param("team").unwrap())
+                });
+            });
+        });
+        router.get("/fail", middleware! { (StatusCode::BAD_REQUEST, "bad input") });
+        router
+    }
+
+    assert_eq!(run(router(), Method::GET, "/api/fail").await,
+               (StatusCode::BAD_REQUEST, r#"{"error": "bad input"}"#.to_string()));
+    assert_eq!(run(router(), Method::GET, "/api/nope").await,
+               (StatusCode::NOT_FOUND, r#"{"error": "no such endpoint"}"#.to_string()));
+    assert_eq!(run(router(), Method::GET, "/api").await,
+               (StatusCode::NOT_FOUND, r#"{"error": "no such endpoint"}"#.to_string()));
+    assert_eq!(run(router(), Method::GET, "/api/v2/ops/nope").await,
+               (StatusCode::NOT_FOUND, "ops has no such endpoint".to_string()));
+    // outside of the scope the global handlers apply
+    assert_eq!(run(router(), Method::GET, "/fail").await, (StatusCode::BAD_REQUEST, "Bad Request".to_string()));
+    assert_eq!(run(router(), Method::GET, "/nope").await, (StatusCode::NOT_FOUND, "".to_string()));
+}
+
+#[tokio::test]
+async fn guards_fall_through_to_the_next_route() {
+    use std::sync::Arc;
+    use hyper::header::{self, HeaderValue};
+    use crate::middleware::MiddlewareStack;
+    use crate::mimes::MediaType;
+    use crate::test_util::{request, run_stack};
+
+    let mut router = Router::new();
+    router.post("/upload", middleware! { "json" }).guard(Guard::content_type(MediaType::Json));
+    router.post("/upload", middleware! { "xml" }).guard(Guard::content_type(MediaType::Xml));
+    router.get("/search", middleware! { "new search" })
+          .guard(Guard::header("x-feature", "new-search"))
+          .guard(Guard::query("q"));
+    router.get("/search", middleware! { "search" });
+    router.get("/page", middleware! { "page 2" }).guard(Guard::query_equals("page", "2"));
+    router.scope("/admin", |admin| {
+        admin.get("/", middleware! { "admin" }).guard(Guard::host("admin.example.com"));
+    });
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(router);
+
+    let run = |method, uri, headers: &[(header::HeaderName, &'static str)]| {
+        let mut req = request(method, uri, Arc::new(()));
+        for (name, value) in headers {
+            req.origin.headers_mut().insert(name.clone(), HeaderValue::from_static(value));
+        }
+        run_stack(&stack, req)
+    };
+    let feature = header::HeaderName::from_static("x-feature");
+
+    assert_eq!(run(Method::POST, "/upload", &[(header::CONTENT_TYPE, "application/json; charset=utf-8")]).await.1, "json");
+    assert_eq!(run(Method::POST, "/upload", &[(header::CONTENT_TYPE, "application/xml")]).await.1, "xml");
+    assert_eq!(run(Method::POST, "/upload", &[(header::CONTENT_TYPE, "text/plain")]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(run(Method::GET, "/search?q=a.b+c", &[(feature.clone(), "new-search")]).await.1, "new search");
+    assert_eq!(run(Method::GET, "/search", &[(feature.clone(), "new-search")]).await.1, "search");
+    assert_eq!(run(Method::GET, "/search?q=a", &[(feature, "old")]).await.1, "search");
+    assert_eq!(run(Method::GET, "/page?page=1&page=2", &[]).await.1, "page 2");
+    assert_eq!(run(Method::GET, "/page?page=1", &[]).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(run(Method::GET, "/admin/", &[(header::HOST, "Admin.example.com:8080")]).await.1, "admin");
+    assert_eq!(run(Method::GET, "/admin/", &[(header::HOST, "example.com")]).await.0, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use hyper::Method;
+use regex::Regex;
+use crate::middleware::{Middleware, MiddlewareResult, ErrorHandler, LocalHandlers, Continue, Halt};
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::{Guard, HttpRouter, Matcher, Route};
+use crate::router::router::Fallback;
+
+/// A group of routes sharing a path prefix and middleware, created with
+/// `Router::scope`.
+///
+/// Middleware added to a scope with `utilize` only runs for requests matching
+/// one of the scope's routes, after the route has been matched and before its
+/// handler. Params in the prefix are available through `Request::param` like
+/// any other route param.
+///
+/// A scope can also have its own not found and error handlers, which apply to
+/// all requests under its prefix.
+pub struct Scope<D: Send + 'static + Sync = ()> {
+    prefix: String,
+    middleware: Vec<Box<dyn Middleware<D> + Send + Sync>>,
+    handlers: LocalHandlers<D>,
+    routes: Vec<Route<D>>,
+    fallbacks: Vec<Fallback<D>>,
+    // How many of the last routes `name` and `guard` apply to.
+    grouped: usize,
+}
+
+impl<D: Send + 'static + Sync> Scope<D> {
+    pub(super) fn new<S: Into<String>>(prefix: S) -> Scope<D> {
+        Scope {
+            prefix: prefix.into(),
+            middleware: Vec::new(),
+            handlers: LocalHandlers::new(),
+            routes: Vec::new(),
+            fallbacks: Vec::new(),
+            grouped: 0
+        }
+    }
+
+    /// Registers a middleware which runs for every route in this scope,
+    /// including nested scopes, in the order it was registered.
+    pub fn utilize<T: Middleware<D>>(&mut self, handler: T) -> &mut Self {
+        self.middleware.push(Box::new(handler));
+        self
+    }
+
+    /// Creates a nested scope, see `Router::scope`.
+    pub fn scope<S, F>(&mut self, prefix: S, f: F) -> &mut Self
+    where S: Into<String>, F: FnOnce(&mut Scope<D>) {
+        let mut scope = Scope::new(prefix);
+        f(&mut scope);
+        let (routes, fallbacks) = scope.into_parts();
+        self.routes.extend(routes);
+        self.fallbacks.extend(fallbacks);
+        self.grouped = usize::from(!self.routes.is_empty());
+        self
+    }
+
+    /// Registers a handler for requests under the scope's prefix which match
+    /// none of its routes, see `Router::not_found`. It runs after the scope
+    /// middleware.
+    pub fn not_found<T: Middleware<D>>(&mut self, handler: T) -> &mut Self {
+        self.handlers.set_not_found(handler);
+        self
+    }
+
+    /// Registers an error handler for errors returned by the scope's
+    /// middleware and routes, see `Router::handle_error`.
+    pub fn handle_error<T: ErrorHandler<D>>(&mut self, handler: T) -> &mut Self {
+        self.handlers.add_error_handler(handler);
+        self
+    }
+
+    /// Names the most recently added route, see `Router::name`.
+    ///
+    /// # Panics
+    /// Panics if no route has been added yet, or if the most recently added
+    /// routes are those of a resource.
+    pub fn name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        assert!(self.grouped <= 1, "Scope::name can't name the routes of a resource");
+        let route = self.routes.last_mut().expect("Scope::name called before adding a route");
+        route.name = Some(name.into());
+        self
+    }
+
+    /// Adds a guard to the most recently added route, or to all routes of
+    /// the most recently added resource, see `Router::guard`.
+    ///
+    /// # Panics
+    /// Panics if no route has been added yet.
+    pub fn guard(&mut self, guard: Guard<D>) -> &mut Self {
+        let start = self.routes.len().checked_sub(self.grouped)
+            .filter(|_| self.grouped > 0)
+            .expect("Scope::guard called before adding a route");
+        for route in &mut self.routes[start..] {
+            route.guards.push(guard.clone());
+        }
+        self
+    }
+
+    // Prefixes all routes and wraps their handlers with the scope middleware
+    // and error handlers. The scope's not found handler becomes a fallback
+    // matching anything under the prefix, after those of nested scopes.
+    pub(super) fn into_parts(self) -> (Vec<Route<D>>, Vec<Fallback<D>>) {
+        let Scope { prefix, middleware, handlers, routes, fallbacks, .. } = self;
+        let has_not_found = handlers.has_not_found();
+        let shared = Arc::new(Shared { middleware, handlers });
+
+        let routes = routes.into_iter().map(|route| {
+            Route {
+                method: route.method,
+                handler: Box::new(Scoped {
+                    shared: shared.clone(),
+                    handler: Some(route.handler)
+                }),
+                name: route.name,
+                matcher: route.matcher.with_prefix(&prefix),
+                guards: route.guards
+            }
+        }).collect();
+
+        let mut fallbacks: Vec<_> = fallbacks.into_iter().map(|fallback| {
+            Fallback {
+                matcher: fallback.matcher.with_prefix(&prefix),
+                handler: Box::new(Scoped {
+                    shared: shared.clone(),
+                    handler: Some(fallback.handler)
+                })
+            }
+        }).collect();
+
+        if has_not_found {
+            let anything = Matcher::from(Regex::new("^(?:/.*)?$").unwrap());
+            fallbacks.push(Fallback {
+                matcher: anything.with_prefix(&prefix),
+                handler: Box::new(Scoped { shared, handler: None })
+            });
+        }
+
+        (routes, fallbacks)
+    }
+}
+
+impl<D: Send + 'static + Sync> HttpRouter<D> for Scope<D> {
+    fn add_route<M: Into<Matcher>, H: Middleware<D>>(&mut self, method: Method, matcher: M, handler: H) -> &mut Self {
+        self.routes.push(Route {
+            method,
+            handler: Box::new(handler),
+            name: None,
+            matcher: matcher.into(),
+            guards: Vec::new()
+        });
+        self.grouped = 1;
+        self
+    }
+
+    fn group(&mut self, count: usize) -> &mut Self {
+        self.grouped = count;
+        self
+    }
+}
+
+struct Shared<D> {
+    middleware: Vec<Box<dyn Middleware<D> + Send + Sync>>,
+    handlers: LocalHandlers<D>,
+}
+
+// Runs the scope middleware before handing off to the route handler, or the
+// scope's not found handler if there is none.
+struct Scoped<D> {
+    shared: Arc<Shared<D>>,
+    handler: Option<Box<dyn Middleware<D> + Send + Sync>>,
+}
+
+impl<D: Send + 'static + Sync> Scoped<D> {
+    async fn invoke_inner(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        for middleware in self.shared.middleware.iter() {
+            match middleware.invoke(req, res).await? {
+                Continue(fresh) => res = fresh,
+                Halt(done) => return Ok(Halt(done))
+            }
+        }
+
+        match self.handler {
+            Some(ref handler) => handler.invoke(req, res).await,
+            None => self.shared.handlers.not_found(req, res).await
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Scoped<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        // `NormalizePath` may turn it on for the scope only
+        let ignore_case = req.ignore_case;
+        let result = self.invoke_inner(req, res).await;
+        let result = self.shared.handlers.handle_errors(result, req);
+        req.ignore_case = ignore_case;
+        result
+    }
+}
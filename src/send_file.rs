@@ -0,0 +1,345 @@
+//! Sending files with conditional and range requests, see
+//! `Response::send_file_for`.
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Utc};
+use hyper::{Body, Method, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::codec::{BytesCodec, FramedRead};
+use crate::middleware::{Halt, MiddlewareResult};
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::response::{mime_from_filename, Response};
+
+// More ranges than this in one request are more likely an attack than a
+// client making good use of them, the whole file is sent instead.
+const MAX_RANGES: usize = 16;
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+impl<D: Send + 'static + Sync> Response<D> {
+    /// Sends the file at `path` in response to `req`, with its media type
+    /// taken from the extension. Unlike `send_file`, this honours the
+    /// conditional and range headers of `req`.
+    ///
+    /// The response carries `Last-Modified` and `ETag`, and is
+    /// `304 Not Modified` when the client's `If-None-Match` or
+    /// `If-Modified-Since` tell that it has the file already. `Range`
+    /// requests get `206 Partial Content` with the requested bytes, which
+    /// are sent as `multipart/byteranges` for several ranges, or
+    /// `416 Range Not Satisfiable` if no range fits the file. `If-Range` is
+    /// honoured. Responses to `HEAD` requests get the headers only.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Request, Response, MiddlewareResult};
+    /// use std::path::Path;
+    ///
+    /// # #[allow(dead_code)]
+    /// async fn handler<D: Send + Sync + 'static>(req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+    ///     let favicon = Path::new("/assets/favicon.ico");
+    ///     res.send_file_for(req, favicon).await
+    /// }
+    /// ```
+    pub async fn send_file_for<P: AsRef<Path>>(mut self, req: &Request<D>, path: P) -> MiddlewareResult<D> {
+        let path = path.as_ref();
+        let (mut file, len, modified) = match open(path).await {
+            Ok(opened) => opened,
+            Err(e) => return self.error(StatusCode::NOT_FOUND, format!("Failed to send file '{:?}': {}", path, e))
+        };
+
+        // Determine content type by file extension or default to binary
+        let mime = mime_from_filename(path).unwrap_or(MediaType::Bin);
+        self.set_header_fallback(&header::CONTENT_TYPE, &mime.into());
+        self.set_header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        let etag = format!("\"{:x}-{:x}\"", modified.map_or(0, |m| m.timestamp()), len);
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            self.set_header(header::ETAG, etag);
+        }
+        if let Some(modified) = modified {
+            if let Ok(modified) = HeaderValue::from_str(&modified.format(HTTP_DATE).to_string()) {
+                self.set_header(header::LAST_MODIFIED, modified);
+            }
+        }
+        self.origin.headers_mut().remove(header::CONTENT_LENGTH);
+        self.start();
+
+        let headers = req.origin.headers();
+        if is_fresh(headers, &etag, modified) {
+            self.set(StatusCode::NOT_MODIFIED);
+            return Ok(Halt(self));
+        }
+
+        let ranges = match headers.get(header::RANGE).and_then(|h| h.to_str().ok()) {
+            Some(range) if *req.origin.method() == Method::GET && if_range_matches(headers, &etag, modified) => {
+                parse_ranges(range, len).filter(|ranges| ranges.len() <= MAX_RANGES)
+            },
+            _ => None
+        };
+        match ranges.as_deref() {
+            None => {
+                self.set(StatusCode::OK);
+                self.set_header(header::CONTENT_LENGTH, HeaderValue::from(len));
+                if *req.origin.method() != Method::HEAD {
+                    self.set_body(Body::wrap_stream(FramedRead::new(file, BytesCodec::new())));
+                }
+                Ok(Halt(self))
+            },
+            Some([]) => {
+                self.set_header(header::CONTENT_RANGE, content_range(None, len));
+                self.error(StatusCode::RANGE_NOT_SATISFIABLE, format!("No range of '{:?}' is satisfiable", path))
+            },
+            Some(&[(start, end)]) => {
+                self.set(StatusCode::PARTIAL_CONTENT);
+                self.set_header(header::CONTENT_RANGE, content_range(Some((start, end)), len));
+                self.set_header(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+                if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                    return self.error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file '{:?}': {}", path, e));
+                }
+                self.set_body(Body::wrap_stream(FramedRead::new(file.take(end - start + 1), BytesCodec::new())));
+                Ok(Halt(self))
+            },
+            Some(ranges) => {
+                let boundary = boundary();
+                let content_type = self.headers().get(header::CONTENT_TYPE).cloned()
+                    .unwrap_or_else(|| MediaType::Bin.into());
+                let parts: Vec<_> = ranges.iter().map(|&(start, end)| {
+                    let part_headers = format!("--{}\r\ncontent-type: {}\r\ncontent-range: {}\r\n\r\n",
+                                               boundary,
+                                               String::from_utf8_lossy(content_type.as_bytes()),
+                                               String::from_utf8_lossy(content_range(Some((start, end)), len).as_bytes()));
+                    (part_headers, start, end)
+                }).collect();
+                let closing = format!("\r\n--{}--\r\n", boundary);
+
+                self.set(StatusCode::PARTIAL_CONTENT);
+                let multipart = format!("multipart/byteranges; boundary={}", boundary);
+                self.set_header(header::CONTENT_TYPE, HeaderValue::from_str(&multipart).unwrap());
+                self.stream(move |mut writer| async move {
+                    for (i, (part_headers, start, end)) in parts.into_iter().enumerate() {
+                        if i > 0 {
+                            writer.write("\r\n").await?;
+                        }
+                        writer.write(part_headers).await?;
+                        file.seek(SeekFrom::Start(start)).await?;
+                        let mut remaining = end - start + 1;
+                        let mut buffer = vec![0; 8192];
+                        while remaining > 0 {
+                            let max = remaining.min(buffer.len() as u64) as usize;
+                            let read = file.read(&mut buffer[..max]).await?;
+                            if read == 0 {
+                                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The file got shorter"));
+                            }
+                            writer.write(Bytes::copy_from_slice(&buffer[..read])).await?;
+                            remaining -= read as u64;
+                        }
+                    }
+                    writer.write(closing).await
+                })
+            }
+        }
+    }
+}
+
+// The file, its length and when it was last modified, to the second.
+async fn open(path: &Path) -> io::Result<(File, u64, Option<DateTime<Utc>>)> {
+    let file = File::open(path).await?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(io::Error::other("Not a file"));
+    }
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since_epoch| DateTime::from_timestamp(since_epoch.as_secs() as i64, 0));
+    Ok((file, metadata.len(), modified))
+}
+
+fn parse_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    let value = value.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok().map(|date| date.with_timezone(&Utc))
+}
+
+// Whether the client's copy is up to date, so `304 Not Modified` will do.
+fn is_fresh(headers: &HeaderMap, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        // weak comparison, `W/` is ignored
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        });
+    }
+    match (headers.get(header::IF_MODIFIED_SINCE).and_then(parse_date), modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false
+    }
+}
+
+// Whether a range request applies, which it doesn't if the client's partial
+// copy is of another version of the file.
+fn if_range_matches(headers: &HeaderMap, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+    let if_range = match headers.get(header::IF_RANGE) {
+        Some(if_range) => if_range,
+        None => return true
+    };
+    // strong comparison, weak tags never match
+    if if_range.as_bytes().starts_with(b"\"") {
+        return if_range == etag;
+    }
+    parse_date(if_range).is_some_and(|date| Some(date) == modified)
+}
+
+// The inclusive byte ranges of a `Range` header for a file of `len` bytes.
+// `None` if the header is invalid and has to be ignored, empty if no range is
+// satisfiable. The ranges are sorted, with overlapping and adjacent ones
+// merged.
+fn parse_ranges(range: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let (unit, specs) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                (suffix > 0 && len > 0).then(|| (len.saturating_sub(suffix), len - 1))
+            },
+            (first, "") => {
+                let first: u64 = first.parse().ok()?;
+                (first < len).then(|| (first, len - 1))
+            },
+            (first, last) => {
+                let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+                if last < first {
+                    return None;
+                }
+                (first < len).then(|| (first, last.min(len - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+
+    // overlapping ranges would send the same bytes over and over
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end))
+        }
+    }
+    Some(merged)
+}
+
+fn content_range(range: Option<(u64, u64)>, len: u64) -> HeaderValue {
+    let value = match range {
+        Some((start, end)) => format!("bytes {}-{}/{}", start, end, len),
+        None => format!("bytes */{}", len)
+    };
+    HeaderValue::from_str(&value).unwrap()
+}
+
+fn boundary() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("nickel-{:x}", nanos)
+}
+
+#[test]
+fn parses_ranges() {
+    assert_eq!(parse_ranges("bytes=0-9", 100), Some(vec![(0, 9)]));
+    assert_eq!(parse_ranges("bytes=90-", 100), Some(vec![(90, 99)]));
+    assert_eq!(parse_ranges("bytes=-10", 100), Some(vec![(90, 99)]));
+    assert_eq!(parse_ranges("bytes=-500", 100), Some(vec![(0, 99)]));
+    assert_eq!(parse_ranges("Bytes=0-0, 50-999", 100), Some(vec![(0, 0), (50, 99)]));
+    assert_eq!(parse_ranges("bytes=100-, -0", 100), Some(vec![]));
+    assert_eq!(parse_ranges("bytes=0-,0-,0-,0-", 100), Some(vec![(0, 99)]));
+    assert_eq!(parse_ranges("bytes=50-59, 0-9, 10-19, 55-70, -5", 100), Some(vec![(0, 19), (50, 70), (95, 99)]));
+    assert_eq!(parse_ranges("bytes=0-", 0), Some(vec![]));
+    assert_eq!(parse_ranges("bytes=9-0", 100), None);
+    assert_eq!(parse_ranges("bytes=a-b", 100), None);
+    assert_eq!(parse_ranges("items=0-9", 100), None);
+}
+
+#[tokio::test]
+async fn sends_files() {
+    use std::sync::Arc;
+    use hyper::{body, Request as HyperRequest};
+    use crate::test_util::response;
+
+    let path = std::env::temp_dir().join(format!("nickel-send-file-{}.txt", std::process::id()));
+    std::fs::write(&path, "0123456789abcdefghij").unwrap();
+
+    let send = |method: Method, headers: Vec<(header::HeaderName, String)>| {
+        let path = path.clone();
+        async move {
+            let mut req = HyperRequest::builder().method(method).uri("/file.txt");
+            for (name, value) in headers {
+                req = req.header(name, value);
+            }
+            let req = Request::from_internal(req.body(Body::empty()).unwrap(), None, Arc::new(()));
+            let res = match response(Arc::new(())).send_file_for(&req, &path).await {
+                Ok(Halt(res)) => res,
+                Err(err) => err.stream.unwrap(),
+                _ => panic!("expected the response to halt")
+            };
+            let (parts, body) = res.origin.into_parts();
+            (parts.status, parts.headers, String::from_utf8(body::to_bytes(body).await.unwrap().to_vec()).unwrap())
+        }
+    };
+
+    let (status, headers, body) = send(Method::GET, vec![]).await;
+    assert_eq!((status, &body[..]), (StatusCode::OK, "0123456789abcdefghij"));
+    assert_eq!(headers[header::CONTENT_LENGTH], "20");
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+    let etag = headers[header::ETAG].to_str().unwrap().to_string();
+    let modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+
+    let (status, _, body) = send(Method::HEAD, vec![]).await;
+    assert_eq!((status, &body[..]), (StatusCode::OK, ""));
+
+    // conditional requests
+    assert_eq!(send(Method::GET, vec![(header::IF_NONE_MATCH, format!("\"x\", W/{}", etag))]).await.0, StatusCode::NOT_MODIFIED);
+    assert_eq!(send(Method::GET, vec![(header::IF_NONE_MATCH, "\"x\"".to_string())]).await.0, StatusCode::OK);
+    assert_eq!(send(Method::GET, vec![(header::IF_MODIFIED_SINCE, modified.clone())]).await.0, StatusCode::NOT_MODIFIED);
+    assert_eq!(send(Method::GET, vec![(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT".to_string())]).await.0, StatusCode::OK);
+
+    // ranges
+    let (status, headers, body) = send(Method::GET, vec![(header::RANGE, "bytes=2-4".to_string())]).await;
+    assert_eq!((status, &body[..]), (StatusCode::PARTIAL_CONTENT, "234"));
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-4/20");
+    assert_eq!(headers[header::CONTENT_LENGTH], "3");
+    assert_eq!(send(Method::GET, vec![(header::RANGE, "bytes=-3".to_string())]).await.2, "hij");
+
+    let (status, headers, body) = send(Method::GET, vec![(header::RANGE, "bytes=0-1,18-".to_string())]).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    let content_type = headers[header::CONTENT_TYPE].to_str().unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    assert_eq!(body, format!("--{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 0-1/20\r\n\r\n01\r\n\
+                              --{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 18-19/20\r\n\r\nij\r\n--{b}--\r\n",
+                             b = boundary));
+
+    let (status, headers, _) = send(Method::GET, vec![(header::RANGE, "bytes=20-".to_string())]).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(headers[header::CONTENT_RANGE], "bytes */20");
+
+    // a range of another version of the file gets all of it
+    let stale = vec![(header::RANGE, "bytes=2-4".to_string()), (header::IF_RANGE, "\"stale\"".to_string())];
+    assert_eq!(send(Method::GET, stale).await.0, StatusCode::OK);
+    let current = vec![(header::RANGE, "bytes=2-4".to_string()), (header::IF_RANGE, etag)];
+    assert_eq!(send(Method::GET, current).await.0, StatusCode::PARTIAL_CONTENT);
+
+    // `send_file` sends the whole file, whatever the request
+    let res = match response(Arc::new(())).send_file(&path).await {
+        Ok(Halt(res)) => res,
+        _ => panic!("expected the response to halt")
+    };
+    assert_eq!(body::to_bytes(res.origin.into_body()).await.unwrap(), "0123456789abcdefghij");
+
+    std::fs::remove_file(&path).unwrap();
+}
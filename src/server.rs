@@ -21,11 +21,11 @@ pub struct Server<D: Send + 'static + Sync> {
 }
 
 impl<D: Sync + Send + 'static> Server<D> {
-    pub fn new(middleware_stack: MiddlewareStack<D>, reload_policy: ReloadPolicy, data: D) -> Server<D> {
+    pub fn new(middleware_stack: Arc<MiddlewareStack<D>>, reload_policy: ReloadPolicy, data: Arc<D>) -> Server<D> {
         Server {
-            middleware_stack: Arc::new(middleware_stack),
+            middleware_stack,
             templates: Arc::new(TemplateCache::with_policy(reload_policy)),
-            shared_data: Arc::new(data)
+            shared_data: data
         }
     }
 
@@ -54,7 +54,8 @@ impl<D: Sync + Send + 'static> Server<D> {
                                                                          req_data2);
                         let nickel_res = response::Response::from_internal(res,
                                                                            res_templates2,
-                                                                           res_data2);
+                                                                           res_data2)
+                            .with_stack(mw2.clone());
                         let final_res = mw2.invoke(nickel_req, nickel_res).await;
                         Ok::<_, Infallible>(final_res)
                     }
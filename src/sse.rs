@@ -0,0 +1,266 @@
+//! Server-Sent Events, pushing a stream of events to the client over a
+//! long-lived response.
+use std::fmt::{self, Write};
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use futures::stream::{Stream, StreamExt};
+use hyper::header::{self, HeaderValue};
+use serde::Serialize;
+use tokio::time::{self, Interval};
+use crate::middleware::MiddlewareResult;
+use crate::mimes::MediaType;
+use crate::request::Request;
+use crate::responder::Responder;
+use crate::response::Response;
+
+impl<D> Request<D> {
+    /// The id of the last event a reconnecting `EventSource` has seen, from
+    /// the `Last-Event-ID` header, so the events after it can be sent again.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.origin.headers().get("last-event-id").and_then(|h| h.to_str().ok())
+    }
+}
+
+/// An event sent by `Sse`.
+///
+/// Line breaks in the data are sent as several `data` fields, which the
+/// client joins back together. The name and id can't contain line breaks,
+/// they are removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// An event of the default type `message` carrying `data`.
+    pub fn data<S: Into<String>>(data: S) -> Event {
+        Event { data: Some(data.into()), ..Event::default() }
+    }
+
+    /// An event carrying `data` as JSON.
+    ///
+    /// # Errors
+    /// Fails if `data` can't be serialized.
+    pub fn json<T: Serialize>(data: &T) -> Result<Event, serde_json::Error> {
+        serde_json::to_string(data).map(Event::data)
+    }
+
+    /// A comment, which clients ignore. Useful to keep connections open.
+    pub fn comment<S: Into<String>>(comment: S) -> Event {
+        Event { comment: Some(comment.into()), ..Event::default() }
+    }
+
+    /// Sets the type of the event, which `EventSource` dispatches on.
+    pub fn event<S: Into<String>>(mut self, event: S) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Sets the id of the event, which the client sends as `Last-Event-ID`
+    /// when it reconnects.
+    pub fn id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+fn single_line(s: String) -> String {
+    if s.contains(['\r', '\n']) {
+        s.replace(['\r', '\n'], "")
+    } else {
+        s
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref comment) = self.comment {
+            for line in comment.lines().flat_map(|line| line.split('\r')) {
+                writeln!(f, ":{}", line)?;
+            }
+        }
+        if let Some(ref event) = self.event {
+            writeln!(f, "event:{}", event)?;
+        }
+        if let Some(ref id) = self.id {
+            writeln!(f, "id:{}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry:{}", retry.as_millis())?;
+        }
+        if let Some(ref data) = self.data {
+            // `lines` drops a trailing empty line, which is data too, and
+            // clients end lines at a bare `\r` as well
+            for line in data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+                writeln!(f, "data:{}", line)?;
+            }
+        }
+        f.write_char('\n')
+    }
+}
+
+type OnDisconnect = Box<dyn FnOnce() + Send>;
+
+/// Responds with a stream of Server-Sent Events.
+///
+/// Events are sent as the stream produces them, until it ends. A comment is
+/// sent when there were no events for a while, so proxies keep the
+/// connection open. A client which has gone away is noticed on the next
+/// event or keep-alive: the stream is dropped then and the `on_disconnect`
+/// callback is called, which is the place to clean up.
+///
+/// # Examples
+/// ```{rust}
+/// use std::time::Duration;
+/// use futures::stream::{self, StreamExt};
+/// use nickel::{Request, Response, MiddlewareResult};
+/// use nickel::sse::{Event, Sse};
+///
+/// # #[allow(dead_code)]
+/// fn ticks(req: &mut Request, res: Response) -> MiddlewareResult {
+///     let start = req.last_event_id().and_then(|id| id.parse().ok()).map_or(0, |id: u64| id + 1);
+///     let events = stream::iter(start..).then(|i| async move {
+///         tokio::time::sleep(Duration::from_secs(1)).await;
+///         Event::data(format!("tick {}", i)).id(i.to_string())
+///     });
+///     res.send(Sse::new(events)
+///         .keep_alive(Some(Duration::from_secs(30)))
+///         .on_disconnect(|| println!("client left")))
+/// }
+/// ```
+pub struct Sse<S> {
+    events: S,
+    keep_alive: Option<Duration>,
+    on_disconnect: Option<OnDisconnect>,
+}
+
+impl<S> Sse<S>
+where S: Stream<Item = Event> + Send + 'static {
+    /// Sends the events of `events`, with a keep-alive comment after 15
+    /// seconds without events.
+    pub fn new(events: S) -> Sse<S> {
+        Sse {
+            events,
+            keep_alive: Some(Duration::from_secs(15)),
+            on_disconnect: None
+        }
+    }
+
+    /// Sets how long to wait without events before sending a keep-alive
+    /// comment, or `None` to never send one.
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+
+    /// Calls `f` when the client disconnects before the stream ends.
+    pub fn on_disconnect<F: FnOnce() + Send + 'static>(mut self, f: F) -> Self {
+        self.on_disconnect = Some(Box::new(f));
+        self
+    }
+}
+
+// Waits for the next keep-alive, or forever without them.
+async fn next_keep_alive(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => { interval.tick().await; },
+        None => futures::future::pending().await
+    }
+}
+
+impl<D, S> Responder<D> for Sse<S>
+where D: Send + 'static + Sync,
+      S: Stream<Item = Event> + Send + 'static {
+    fn respond(self, mut res: Response<D>) -> MiddlewareResult<D> {
+        res.set(MediaType::EventStream);
+        res.set_header(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+        let Sse { events, keep_alive, on_disconnect } = self;
+        res.stream(move |mut writer| async move {
+            let mut events: Pin<Box<S>> = Box::pin(events);
+            let mut interval = keep_alive.map(|period| time::interval_at(time::Instant::now() + period, period));
+
+            loop {
+                let chunk = tokio::select! {
+                    event = events.next() => match event {
+                        Some(event) => event.to_string(),
+                        None => return Ok::<_, io::Error>(())
+                    },
+                    _ = next_keep_alive(&mut interval) => ":\n\n".to_string()
+                };
+
+                if writer.write(chunk).await.is_err() {
+                    drop(events);
+                    if let Some(on_disconnect) = on_disconnect {
+                        on_disconnect();
+                    }
+                    return Ok(());
+                }
+                if let Some(ref mut interval) = interval {
+                    interval.reset();
+                }
+            }
+        })
+    }
+}
+
+#[test]
+fn frames_events() {
+    assert_eq!(Event::data("hello").to_string(), "data:hello\n\n");
+    assert_eq!(Event::data("a\nb\r\n\nc\n").event("up\ndate").id("7").retry(Duration::from_secs(3)).to_string(),
+               "event:update\nid:7\nretry:3000\ndata:a\ndata:b\ndata:\ndata:c\ndata:\n\n");
+    assert_eq!(Event::data("a\rdata:b\r\rc\r").to_string(), "data:a\ndata:data:b\ndata:\ndata:c\ndata:\n\n");
+    assert_eq!(Event::comment("ping").to_string(), ":ping\n\n");
+    assert_eq!(Event::comment("a\rdata:b").to_string(), ":a\n:data:b\n\n");
+    assert_eq!(Event::json(&vec![1, 2]).unwrap().to_string(), "data:[1,2]\n\n");
+}
+
+#[tokio::test]
+async fn streams_events() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use futures::stream;
+    use hyper::{body, Method};
+    use crate::Halt;
+    use crate::test_util::{request, response};
+
+    let mut req = request(Method::GET, "/events", Arc::new(()));
+    req.origin.headers_mut().insert("last-event-id", HeaderValue::from_static("41"));
+    assert_eq!(req.last_event_id(), Some("41"));
+
+    let events = stream::iter(vec![Event::data("a").id("42"), Event::data("b").event("note")])
+        .chain(stream::once(async {
+            time::sleep(Duration::from_millis(30)).await;
+            Event::data("c")
+        }));
+    let res = match response(Arc::new(())).send(Sse::new(events).keep_alive(Some(Duration::from_millis(10)))) {
+        Ok(Halt(res)) => res,
+        _ => panic!("expected the response to halt")
+    };
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+    let body = body::to_bytes(res.origin.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.starts_with("id:42\ndata:a\n\nevent:note\ndata:b\n\n:\n\n"), "{:?}", body);
+    assert!(body.ends_with("data:c\n\n"), "{:?}", body);
+
+    let disconnected = Arc::new(AtomicBool::new(false));
+    let flag = disconnected.clone();
+    let sse = Sse::new(stream::pending())
+        .keep_alive(Some(Duration::from_millis(5)))
+        .on_disconnect(move || flag.store(true, Ordering::SeqCst));
+    let res = response(Arc::new(())).send(sse);
+    drop(res);
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(disconnected.load(Ordering::SeqCst));
+}
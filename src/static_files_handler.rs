@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use std::path::{Path, PathBuf};
 use std::io::ErrorKind::NotFound;
-use std::fs;
+use tokio::fs;
 
 use hyper::Method;
 
@@ -22,7 +22,10 @@ impl<D: Send + 'static + Sync> Middleware<D> for StaticFilesHandler {
     async fn invoke(&self, req: &mut Request<D>, res: Response<D>)
             -> MiddlewareResult<D> {
         match *req.origin.method() {
-            Method::GET | Method::HEAD => self.with_file(self.extract_path(req), res).await,
+            Method::GET | Method::HEAD => {
+                let path = self.extract_path(req).to_string();
+                self.with_file(req, path, res).await
+            },
             _ => res.next_middleware()
         }
     }
@@ -47,7 +50,7 @@ impl StaticFilesHandler {
         }
     }
 
-    fn extract_path<'a, D>(&self, req: &'a mut Request<D>) -> &'a str {
+    fn extract_path<'a, D>(&self, req: &'a Request<D>) -> &'a str {
         let path = req.path_without_query();
         debug!("{:?} {:?}{:?}", req.origin.method(), self.root_path.display(), path);
         
@@ -58,6 +61,7 @@ impl StaticFilesHandler {
     }
 
     async fn with_file<D: Send + 'static + Sync, P>(&self,
+                                              req: &Request<D>,
                                               relative_path: P,
                                               res: Response<D>)
                                               -> MiddlewareResult<D> where P: AsRef<Path> {
@@ -68,8 +72,8 @@ impl StaticFilesHandler {
         }
         
         let path = self.root_path.join(path);
-        match fs::metadata(&path) {
-            Ok(ref attr) if attr.is_file() => return res.send_file(&path).await,
+        match fs::metadata(&path).await {
+            Ok(ref attr) if attr.is_file() => return res.send_file_for(req, &path).await,
             Err(ref e) if e.kind() != NotFound => debug!("Error getting metadata \
                                                           for file '{:?}': {:?}",
                                                          path, e),
@@ -0,0 +1,46 @@
+//! Helpers to run requests through middleware in unit tests.
+use std::sync::Arc;
+use hyper::{Body, Method, Request as HyperRequest, Response as HyperResponse, StatusCode};
+use hyper::body;
+use crate::default_error_handler::DefaultErrorHandler;
+use crate::middleware::{Middleware, MiddlewareStack};
+use crate::request::Request;
+use crate::response::Response;
+use crate::template_cache::{ReloadPolicy, TemplateCache};
+
+pub fn request<D>(method: Method, uri: &str, data: Arc<D>) -> Request<D> {
+    let origin = HyperRequest::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+    Request::from_internal(origin, None, data)
+}
+
+pub fn response<D: Send + 'static + Sync>(data: Arc<D>) -> Response<D> {
+    let origin = HyperResponse::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
+    Response::from_internal(origin, Arc::new(TemplateCache::with_policy(ReloadPolicy::Never)), data)
+}
+
+/// Runs a request through a stack made of `middleware` and the default error
+/// handler, returning the status and body of the response.
+pub async fn run<M: Middleware<()>>(middleware: M, method: Method, uri: &str) -> (StatusCode, String) {
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(DefaultErrorHandler);
+    stack.add_middleware(middleware);
+    run_stack(&stack, request(method, uri, Arc::new(()))).await
+}
+
+pub async fn run_stack<D: Send + 'static + Sync>(stack: &MiddlewareStack<D>, req: Request<D>) -> (StatusCode, String) {
+    let data = req.server_data();
+    body_of(stack.invoke(req, response(data)).await).await
+}
+
+/// Like `run_stack`, but the response knows its stack like it does in a
+/// running server, so forwards and sub-requests work.
+pub async fn run_shared<D: Send + 'static + Sync>(stack: &Arc<MiddlewareStack<D>>, req: Request<D>) -> (StatusCode, String) {
+    let res = response(req.server_data()).with_stack(stack.clone());
+    body_of(stack.invoke(req, res).await).await
+}
+
+async fn body_of(res: HyperResponse<Body>) -> (StatusCode, String) {
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
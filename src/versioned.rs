@@ -0,0 +1,358 @@
+//! Serving several versions of an API side by side.
+use async_trait::async_trait;
+use hyper::StatusCode;
+use hyper::header::{self, HeaderName, HeaderValue};
+use mime::Mime;
+use regex::Regex;
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mount::strip_prefix;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::RouteInfo;
+
+lazy_static! {
+    static ref PATH_VERSION: Regex = Regex::new(r"^/[vV](\d+(?:\.\d+)?)(/.*)$").unwrap();
+}
+
+// A major and optional minor version asked for by a request
+type Requested = (u32, Option<u32>);
+
+enum VersionSource {
+    Path,
+    Header(HeaderName),
+    MediaType(String),
+}
+
+struct Version<D> {
+    name: String,
+    major: u32,
+    minor: u32,
+    handler: Box<dyn Middleware<D> + Send + Sync>,
+    deprecated: bool,
+    sunset: Option<String>,
+}
+
+/// Dispatches to one of several versions of an API, each of which is a
+/// middleware, usually a `Router`.
+///
+/// The requested version is taken from the first of the configured sources
+/// which has one:
+///
+/// * a path prefix like `/v2/users`, which is stripped like by `Mount`,
+/// * a header like `Accept-Version: 2`,
+/// * a vendor media type in `Accept`, like `application/vnd.acme.v2+json`
+///   or `application/vnd.acme+json; version=2`.
+///
+/// Versions are `major` or `major.minor`. A request gets the newest version
+/// with the same major version and a minor version not above the requested
+/// one, so asking for `2` or `2.3` gets `2.1` if that is the newest `2.x`.
+/// Requests without a version get the newest version. If no version is
+/// compatible, a version from the path is passed on to the next middleware
+/// and a version from a header gets `406 Not Acceptable`.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Versioned};
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     let mut v1 = Nickel::router();
+///     v1.get("/users", middleware! { "users v1" });
+///     let mut v2 = Nickel::router();
+///     v2.get("/users", middleware! { "users v2" });
+///
+///     server.utilize(Versioned::new()
+///         .from_path()
+///         .from_header("Accept-Version")
+///         .from_media_type("acme")
+///         .version("1", v1)
+///         .deprecated("1", Some("Sat, 01 Nov 2025 00:00:00 GMT"))
+///         .version("2", v2));
+/// }
+/// ```
+pub struct Versioned<D = ()> {
+    sources: Vec<VersionSource>,
+    // Newest first
+    versions: Vec<Version<D>>,
+}
+
+impl<D: Send + 'static + Sync> Versioned<D> {
+    pub fn new() -> Versioned<D> {
+        Versioned {
+            sources: Vec::new(),
+            versions: Vec::new()
+        }
+    }
+
+    /// Takes the version from a path prefix like `/v2`.
+    pub fn from_path(mut self) -> Self {
+        self.sources.push(VersionSource::Path);
+        self
+    }
+
+    /// Takes the version from the header `name`, e.g. `Accept-Version`.
+    ///
+    /// # Panics
+    /// Panics if `name` isn't a valid header name.
+    pub fn from_header(mut self, name: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .unwrap_or_else(|_| panic!("Invalid version header name: {}", name));
+        self.sources.push(VersionSource::Header(name));
+        self
+    }
+
+    /// Takes the version from the `Accept` header, using vendor media types
+    /// of `vendor`, e.g. `application/vnd.{vendor}.v2+json`.
+    pub fn from_media_type(mut self, vendor: &str) -> Self {
+        self.sources.push(VersionSource::MediaType(format!("vnd.{}", vendor)));
+        self
+    }
+
+    /// Serves `version` with `handler`.
+    ///
+    /// # Panics
+    /// Panics if `version` isn't `major` or `major.minor`, or has already
+    /// been added.
+    pub fn version<M: Middleware<D>>(mut self, version: &str, handler: M) -> Self {
+        let (major, minor) = parse_version(version)
+            .unwrap_or_else(|| panic!("Invalid API version: {}", version));
+        let minor = minor.unwrap_or(0);
+        assert!(!self.versions.iter().any(|v| (v.major, v.minor) == (major, minor)),
+                "API version {} added twice", version);
+
+        let position = self.versions.iter().position(|v| (v.major, v.minor) < (major, minor));
+        let version = Version {
+            name: version.trim_start_matches(['v', 'V']).to_string(),
+            major,
+            minor,
+            handler: Box::new(handler),
+            deprecated: false,
+            sunset: None
+        };
+        self.versions.insert(position.unwrap_or(self.versions.len()), version);
+        self
+    }
+
+    /// Marks `version` as deprecated. Its responses get a `Deprecation`
+    /// header, and a `Sunset` header with `sunset` if given, which should be
+    /// an HTTP date.
+    ///
+    /// # Panics
+    /// Panics if `version` hasn't been added.
+    pub fn deprecated(mut self, version: &str, sunset: Option<&str>) -> Self {
+        let (major, minor) = parse_version(version)
+            .unwrap_or_else(|| panic!("Invalid API version: {}", version));
+        let minor = minor.unwrap_or(0);
+        let version = self.versions.iter_mut().find(|v| (v.major, v.minor) == (major, minor))
+            .unwrap_or_else(|| panic!("Deprecated API version {} hasn't been added", version));
+        version.deprecated = true;
+        version.sunset = sunset.map(|s| s.to_string());
+        self
+    }
+
+    // The version asked for by the first source which has one, and whether
+    // it came from the path.
+    fn requested(&self, req: &Request<D>) -> Result<Option<(Requested, bool)>, String> {
+        for source in &self.sources {
+            let version = match source {
+                VersionSource::Path => {
+                    let captures = PATH_VERSION.captures(req.path_without_query());
+                    // no named group, it would become a param of the request
+                    if let Some(version) = captures.and_then(|c| parse_version(&c[1])) {
+                        return Ok(Some((version, true)));
+                    }
+                    continue
+                },
+                VersionSource::Header(name) => match req.origin.headers().get(name) {
+                    Some(value) => value.to_str().ok().and_then(parse_version)
+                        .ok_or_else(|| format!("Invalid API version in {}", name))?,
+                    None => continue
+                },
+                VersionSource::MediaType(vendor) => match accepted_version(req, vendor) {
+                    Some(version) => version?,
+                    None => continue
+                }
+            };
+            return Ok(Some((version, false)));
+        }
+        Ok(None)
+    }
+
+    fn select(&self, requested: Option<Requested>) -> Option<&Version<D>> {
+        match requested {
+            None => self.versions.first(),
+            Some((major, minor)) => self.versions.iter().find(|v| {
+                v.major == major && minor.is_none_or(|minor| v.minor <= minor)
+            })
+        }
+    }
+}
+
+impl<D: Send + 'static + Sync> Default for Versioned<D> {
+    fn default() -> Self {
+        Versioned::new()
+    }
+}
+
+// Parses `2`, `2.1` or `v2`.
+fn parse_version(version: &str) -> Option<Requested> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    let mut parts = version.splitn(2, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = match parts.next() {
+        Some(minor) => Some(minor.parse().ok()?),
+        None => None
+    };
+    Some((major, minor))
+}
+
+// The version of the first vendor media type in `Accept`, if any.
+fn accepted_version<D>(req: &Request<D>, vendor: &str) -> Option<Result<Requested, String>> {
+    let accept = req.origin.headers().get_all(header::ACCEPT).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| range.trim().parse::<Mime>().ok());
+
+    for mime in accept {
+        let subtype = mime.subtype().as_str();
+        let version = if let Some(version) = subtype.strip_prefix(vendor).and_then(|s| s.strip_prefix('.')) {
+            version.to_string()
+        } else if subtype == vendor {
+            match mime.get_param("version") {
+                Some(version) => version.as_str().to_string(),
+                None => continue
+            }
+        } else {
+            continue
+        };
+        return Some(parse_version(&version).ok_or_else(|| format!("Invalid API version in {}", mime)));
+    }
+    None
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Versioned<D> {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        let (requested, from_path) = match self.requested(req) {
+            Ok(Some((version, from_path))) => (Some(version), from_path),
+            Ok(None) => (None, false),
+            Err(message) => return res.error(StatusCode::BAD_REQUEST, message)
+        };
+        let version = match self.select(requested) {
+            Some(version) => version,
+            None if from_path => return res.next_middleware(),
+            None => return res.error(StatusCode::NOT_ACCEPTABLE, "Unsupported API version")
+        };
+        let stripped = if from_path {
+            match strip_prefix(&PATH_VERSION, req) {
+                Some(stripped) => Some(stripped),
+                None => return res.next_middleware()
+            }
+        } else {
+            None
+        };
+
+        // set once a version answers, the next middleware mustn't get them
+        for source in &self.sources {
+            let vary = match source {
+                VersionSource::Path => continue,
+                VersionSource::Header(name) => HeaderValue::from_str(name.as_str()).unwrap(),
+                VersionSource::MediaType(_) => HeaderValue::from_static("Accept")
+            };
+            res.headers_mut().append(header::VARY, vary);
+        }
+        if version.deprecated {
+            res.headers_mut().insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+            if let Some(sunset) = version.sunset.as_ref().and_then(|s| HeaderValue::from_str(s).ok()) {
+                res.headers_mut().insert(HeaderName::from_static("sunset"), sunset);
+            }
+        }
+
+        let (new_uri, mounted) = match stripped {
+            Some(stripped) => stripped,
+            None => return version.handler.invoke(req, res).await
+        };
+        let original = std::mem::replace(req.origin.uri_mut(), new_uri);
+        let outer = req.mounted.replace(mounted);
+        let result = version.handler.invoke(req, res).await;
+        *req.origin.uri_mut() = original;
+        req.mounted = outer;
+        result
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let from_path = self.sources.iter().any(|s| matches!(s, VersionSource::Path));
+        self.versions.iter().flat_map(|version| {
+            version.handler.routes().into_iter().map(move |mut route| {
+                route.guards.push(format!("api version {}", version.name));
+                if from_path {
+                    route.with_prefix(&format!("/v{}/", version.name))
+                } else {
+                    route
+                }
+            })
+        }).collect()
+    }
+}
+
+#[tokio::test]
+async fn selects_versions() {
+    use std::sync::Arc;
+    use hyper::{Body, Method, Response as HyperResponse};
+    use crate::{HttpRouter, Router};
+    use crate::default_error_handler::DefaultErrorHandler;
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, response};
+
+    fn router(name: &'static str) -> Router {
+        let mut router = Router::new();
+        router.get("/users", middleware! { |request| format!("{} {}", name, request.base_path()) });
+        router.get("/version", middleware! { |request| format!("{:?}", request.param("version")) });
+        router
+    }
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(DefaultErrorHandler);
+    stack.add_middleware(Versioned::new()
+        .from_path()
+        .from_header("Accept-Version")
+        .from_media_type("acme")
+        .version("1.0", router("v1"))
+        .version("2.1", router("v2.1"))
+        .version("2", router("v2"))
+        .deprecated("1", Some("Sat, 01 Nov 2025 00:00:00 GMT")));
+
+    let run = |uri, headers: &[(&'static str, &'static str)]| {
+        let mut req = request(Method::GET, uri, Arc::new(()));
+        for &(name, value) in headers {
+            req.origin.headers_mut().insert(name, HeaderValue::from_static(value));
+        }
+        stack.invoke(req, response(Arc::new(())))
+    };
+    async fn body(res: HyperResponse<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    assert_eq!(body(run("/users", &[]).await).await, "v2.1 ");
+    assert_eq!(body(run("/v2/users", &[]).await).await, "v2.1 /v2");
+    assert_eq!(body(run("/v2.0/users", &[]).await).await, "v2 /v2.0");
+    assert_eq!(body(run("/users", &[("accept-version", "2.5")]).await).await, "v2.1 ");
+    assert_eq!(body(run("/users", &[("accept", "application/vnd.acme.v2.0+json")]).await).await, "v2 ");
+    assert_eq!(body(run("/users", &[("accept", "application/vnd.acme+json; version=1")]).await).await, "v1 ");
+    assert_eq!(run("/users", &[("accept-version", "3")]).await.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(run("/users", &[("accept-version", "latest")]).await.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(body(run("/v2/version", &[]).await).await, "None");
+
+    // the next middleware gets no headers of a version
+    let v3 = run("/v3/users", &[]).await;
+    assert_eq!(v3.status(), StatusCode::NOT_FOUND);
+    assert!(v3.headers().get(header::VARY).is_none());
+
+    let v1 = run("/v1/users", &[]).await;
+    assert_eq!(v1.headers()["deprecation"], "true");
+    assert_eq!(v1.headers()["sunset"], "Sat, 01 Nov 2025 00:00:00 GMT");
+    let vary: Vec<_> = v1.headers().get_all(header::VARY).iter().collect();
+    assert_eq!(vary, vec!["accept-version", "Accept"]);
+    assert!(run("/users", &[]).await.headers().get("deprecation").is_none());
+}
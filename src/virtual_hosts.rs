@@ -0,0 +1,220 @@
+//! Dispatching requests by their `Host`.
+use async_trait::async_trait;
+use hyper::header;
+use crate::middleware::{Middleware, MiddlewareResult};
+use crate::mount::mount_state;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::RouteInfo;
+
+enum HostPattern {
+    Exact(String),
+    // The `.example.com` of `*.example.com`
+    Wildcard(String),
+}
+
+/// Dispatches requests to a middleware, usually a `Router` or a `Nickel` app,
+/// by the host they are for.
+///
+/// Hosts are either exact, like `example.com`, or wildcards like
+/// `*.example.com`, which match any subdomain but not `example.com` itself.
+/// Exact hosts are tried first, then wildcards with the longest suffix. The
+/// part matched by `*` is available as the `subdomain` param. Hosts are
+/// compared without case and port.
+///
+/// Requests for other hosts go to the default, if there is one, and are
+/// passed on to the next middleware otherwise.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, VirtualHosts};
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     let mut www = Nickel::router();
+///     www.get("/", middleware! { "home" });
+///     let mut tenants = Nickel::router();
+///     tenants.get("/", middleware! { |request|
+///         format!("tenant {}", request.param("subdomain").unwrap())
+///     });
+///
+///     server.utilize(VirtualHosts::new()
+///         .host("www.example.com", www)
+///         .host("*.example.com", tenants)
+///         .default_host(middleware! { "unknown host" }));
+/// }
+/// ```
+pub struct VirtualHosts<D = ()> {
+    hosts: Vec<(HostPattern, Box<dyn Middleware<D> + Send + Sync>)>,
+    default: Option<Box<dyn Middleware<D> + Send + Sync>>,
+}
+
+impl<D: Send + 'static + Sync> VirtualHosts<D> {
+    pub fn new() -> VirtualHosts<D> {
+        VirtualHosts {
+            hosts: Vec::new(),
+            default: None
+        }
+    }
+
+    /// Serves requests for `host`, which may start with `*.` to match any
+    /// subdomain, with `handler`.
+    ///
+    /// # Panics
+    /// Panics if `host` has a `*` anywhere else.
+    pub fn host<M: Middleware<D>>(mut self, host: &str, handler: M) -> Self {
+        let name = host.trim_end_matches('.').to_ascii_lowercase();
+        let domain = name.strip_prefix("*.");
+        assert!(!domain.unwrap_or(&name).contains('*'),
+                "Invalid virtual host {}, only a leading `*.` is supported", host);
+        // the dot stays in the suffix, so `*.example.com` doesn't match
+        // `badexample.com`
+        let pattern = match domain {
+            Some(domain) => HostPattern::Wildcard(format!(".{}", domain)),
+            None => HostPattern::Exact(name)
+        };
+        self.hosts.push((pattern, Box::new(handler)));
+        self
+    }
+
+    /// Serves requests for any other host with `handler`.
+    pub fn default_host<M: Middleware<D>>(mut self, handler: M) -> Self {
+        self.default = Some(Box::new(handler));
+        self
+    }
+
+    // The handler for `host` and the subdomain matched by a wildcard.
+    fn find(&self, host: &str) -> Option<(&(dyn Middleware<D> + Send + Sync), Option<String>)> {
+        let exact = self.hosts.iter().find(|(pattern, _)| {
+            matches!(pattern, HostPattern::Exact(exact) if exact == host)
+        });
+        if let Some((_, handler)) = exact {
+            return Some((&**handler, None));
+        }
+
+        self.hosts.iter().filter_map(|(pattern, handler)| match pattern {
+            HostPattern::Wildcard(suffix) => {
+                let subdomain = host.strip_suffix(&suffix[..]).filter(|s| !s.is_empty())?;
+                Some((suffix.len(), &**handler, subdomain))
+            },
+            HostPattern::Exact(_) => None
+        }).max_by_key(|&(len, _, _)| len)
+          .map(|(_, handler, subdomain)| (handler, Some(subdomain.to_string())))
+    }
+}
+
+impl<D: Send + 'static + Sync> Default for VirtualHosts<D> {
+    fn default() -> Self {
+        VirtualHosts::new()
+    }
+}
+
+// The requested host without port, in lower case.
+pub(crate) fn request_host<D>(req: &Request<D>) -> Option<String> {
+    request_authority(req).map(|authority| split_host(authority).0)
+}
+
+// The `Host` of the request, or the authority of its uri.
+pub(crate) fn request_authority<D>(req: &Request<D>) -> Option<&str> {
+    req.origin.headers().get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.origin.uri().authority().map(|a| a.as_str()))
+}
+
+// Splits `authority` into the host, without a trailing dot and in lower
+// case, and the port.
+pub(crate) fn split_host(authority: &str) -> (String, Option<&str>) {
+    let (host, port) = match authority.rfind(':') {
+        // not the colons of an IPv6 address like `[::1]`
+        Some(i) if !authority[i..].contains(']') => (&authority[..i], Some(&authority[i + 1..])),
+        _ => (authority, None)
+    };
+    (host.trim_end_matches('.').to_ascii_lowercase(), port)
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for VirtualHosts<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        let found = request_host(req).and_then(|host| self.find(&host));
+        let (handler, subdomain) = match (found, &self.default) {
+            (Some(found), _) => found,
+            (None, Some(default)) => (&**default, None),
+            (None, None) => return res.next_middleware()
+        };
+
+        let subdomain = match subdomain {
+            Some(subdomain) => subdomain,
+            None => return handler.invoke(req, res).await
+        };
+
+        let mut mounted = mount_state(req);
+        mounted.params.push(("subdomain".to_string(), subdomain));
+        let outer = req.mounted.replace(mounted);
+        let result = handler.invoke(req, res).await;
+        req.mounted = outer;
+        result
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let hosts = self.hosts.iter().map(|(pattern, handler)| {
+            let host = match pattern {
+                HostPattern::Exact(host) => host.clone(),
+                HostPattern::Wildcard(suffix) => format!("*{}", suffix)
+            };
+            (Some(host), handler)
+        });
+        hosts.chain(self.default.iter().map(|handler| (None, handler))).flat_map(|(host, handler)| {
+            handler.routes().into_iter().map(move |mut route| {
+                if let Some(ref host) = host {
+                    route.guards.push(format!("host {}", host));
+                }
+                route
+            })
+        }).collect()
+    }
+}
+
+#[tokio::test]
+async fn dispatches_by_host() {
+    use std::sync::Arc;
+    use hyper::{Method, StatusCode};
+    use hyper::header::HeaderValue;
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{request, run_stack};
+
+    let mut stack = MiddlewareStack::new();
+    stack.add_middleware(VirtualHosts::new()
+        .host("*.example.com", middleware! { |request| format!("tenant {}", request.param("subdomain").unwrap()) })
+        .host("www.example.com", middleware! { "www" })
+        .host("*.eu.example.com", middleware! { |request| format!("eu tenant {}", request.param("subdomain").unwrap()) })
+        .host("api.example.org", middleware! { "api" }));
+    let mut fallback = MiddlewareStack::new();
+    fallback.add_middleware(VirtualHosts::new()
+        .host("api.example.org", middleware! { "api" })
+        .default_host(middleware! { "default" }));
+
+    let run = |stack, host| {
+        let mut req = request(Method::GET, "/", Arc::new(()));
+        if let Some(host) = host {
+            req.origin.headers_mut().insert(header::HOST, HeaderValue::from_static(host));
+        }
+        run_stack(stack, req)
+    };
+
+    assert_eq!(run(&stack, Some("www.example.com")).await.1, "www");
+    assert_eq!(run(&stack, Some("Acme.Example.com:8080")).await.1, "tenant acme");
+    assert_eq!(run(&stack, Some("a.b.example.com")).await.1, "tenant a.b");
+    assert_eq!(run(&stack, Some("acme.eu.example.com")).await.1, "eu tenant acme");
+    assert_eq!(run(&stack, Some("api.example.org.")).await.1, "api");
+    assert_eq!(run(&stack, Some("example.com")).await, (StatusCode::NOT_FOUND, "".to_string()));
+    assert_eq!(run(&stack, None).await, (StatusCode::NOT_FOUND, "".to_string()));
+    assert_eq!(run(&fallback, Some("example.com")).await.1, "default");
+    assert_eq!(run(&fallback, None).await.1, "default");
+}
+
+#[test]
+#[should_panic(expected = "only a leading `*.` is supported")]
+fn rejects_other_wildcards() {
+    VirtualHosts::<()>::new().host("*example.com", middleware! { "tenant" });
+}
@@ -0,0 +1,171 @@
+//! Accepting WebSocket connections, see `Response::upgrade_websocket`.
+use std::future::Future;
+use std::sync::Arc;
+use hyper::{Method, StatusCode};
+use hyper::header::{self, HeaderValue};
+use hyper::upgrade::Upgraded;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use crate::middleware::MiddlewareResult;
+use crate::request::Request;
+use crate::response::{requests_upgrade, Response};
+
+pub use tokio_tungstenite::tungstenite::{Error, Message};
+
+/// An accepted WebSocket connection. It is a `Stream` of the messages the
+/// client sends and a `Sink` for the messages sent to it, `StreamExt::split`
+/// separates the two.
+pub type WebSocket = WebSocketStream<Upgraded>;
+
+const VERSION: &str = "13";
+
+impl<D: Send + 'static + Sync> Response<D> {
+    /// Accepts the WebSocket handshake of `req`, then runs `handler` with the
+    /// connection and the server data on a task of its own.
+    ///
+    /// Call this from a route handler, so the route params and any middleware
+    /// before it, like authentication, apply before the connection is
+    /// accepted. Invalid handshakes get `400 Bad Request`, requests which
+    /// aren't handshakes or use an unsupported version of the protocol get
+    /// `426 Upgrade Required`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use futures::{SinkExt, StreamExt};
+    /// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+    /// use nickel::websocket::Message;
+    ///
+    /// fn chat(req: &mut Request, res: Response) -> MiddlewareResult {
+    ///     let room = req.param("room").unwrap().to_string();
+    ///     res.upgrade_websocket(req, move |mut ws, _data| async move {
+    ///         while let Some(Ok(Message::Text(text))) = ws.next().await {
+    ///             let reply = format!("{}: {}", room, text);
+    ///             if ws.send(Message::Text(reply)).await.is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     })
+    /// }
+    ///
+    /// let mut server = Nickel::new();
+    /// server.get("/chat/:room", chat);
+    /// ```
+    // like every handler, the error carries the response back
+    #[allow(clippy::result_large_err)]
+    pub fn upgrade_websocket<F, Fut>(mut self, req: &mut Request<D>, handler: F) -> MiddlewareResult<D>
+    where F: FnOnce(WebSocket, Arc<D>) -> Fut + Send + 'static,
+          Fut: Future<Output = ()> + Send + 'static {
+        // requests which aren't upgrades at all are turned down by `upgrade`
+        if requests_upgrade(req, "websocket") {
+            if req.origin.method() != Method::GET {
+                return self.error(StatusCode::BAD_REQUEST, "A WebSocket handshake must be a GET request");
+            }
+
+            let headers = req.origin.headers();
+            if headers.get(header::SEC_WEBSOCKET_VERSION).is_none_or(|v| v != VERSION) {
+                self.set_header(header::SEC_WEBSOCKET_VERSION, HeaderValue::from_static(VERSION));
+                return self.error(StatusCode::UPGRADE_REQUIRED, "Unsupported WebSocket version");
+            }
+
+            let accept = match headers.get(header::SEC_WEBSOCKET_KEY) {
+                Some(key) => derive_accept_key(key.as_bytes()),
+                None => return self.error(StatusCode::BAD_REQUEST, "A WebSocket handshake needs a Sec-WebSocket-Key")
+            };
+            match HeaderValue::from_str(&accept) {
+                Ok(accept) => self.set_header(header::SEC_WEBSOCKET_ACCEPT, accept),
+                Err(e) => return self.error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            };
+        }
+
+        self.upgrade(req, "websocket", move |upgraded, data| async move {
+            let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            handler(ws, data).await
+        })
+    }
+}
+
+#[tokio::test]
+async fn accepts_websockets() {
+    use futures::{SinkExt, StreamExt};
+    use hyper::{Body, Request as HyperRequest};
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::{HttpRouter, Router};
+    use crate::default_error_handler::DefaultErrorHandler;
+    use crate::middleware::MiddlewareStack;
+    use crate::test_util::{response, run_stack};
+
+    fn chat(req: &mut Request<String>, res: Response<String>) -> MiddlewareResult<String> {
+        let room = req.param("room").unwrap().to_string();
+        res.upgrade_websocket(req, move |mut ws, greeting| async move {
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                let reply = format!("{} {}: {}", greeting, room, text);
+                if ws.send(Message::Text(reply)).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    let mut router = Router::new();
+    router.get("/chat/:room", chat);
+    let mut stack = MiddlewareStack::new();
+    stack.add_error_handler(DefaultErrorHandler);
+    stack.add_middleware(router);
+    let stack = Arc::new(stack);
+    let data = Arc::new("hello".to_string());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_stack = stack.clone();
+    let server_data = data.clone();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let service = service_fn(move |req| {
+            let (stack, data) = (server_stack.clone(), server_data.clone());
+            async move {
+                let req = Request::from_internal(req, None, data.clone());
+                Ok::<_, hyper::Error>(stack.invoke(req, response(data)).await)
+            }
+        });
+        Http::new().serve_connection(socket, service).with_upgrades().await.unwrap();
+    });
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let (mut ws, handshake) = tokio_tungstenite::client_async(format!("ws://{}/chat/lobby", addr), socket).await.unwrap();
+    assert_eq!(handshake.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(handshake.headers()[header::UPGRADE], "websocket");
+    ws.send(Message::Text("hi".to_string())).await.unwrap();
+    assert_eq!(ws.next().await.unwrap().unwrap(), Message::Text("hello lobby: hi".to_string()));
+
+    let handshake = |version: Option<&'static str>, key: Option<&'static str>, upgrade: bool| {
+        let mut req = HyperRequest::get("/chat/lobby");
+        if upgrade {
+            req = req.header(header::CONNECTION, "keep-alive, Upgrade").header(header::UPGRADE, "websocket");
+        }
+        if let Some(version) = version {
+            req = req.header(header::SEC_WEBSOCKET_VERSION, version);
+        }
+        if let Some(key) = key {
+            req = req.header(header::SEC_WEBSOCKET_KEY, key);
+        }
+        let req = Request::from_internal(req.body(Body::empty()).unwrap(), None, data.clone());
+        run_stack(&stack, req)
+    };
+    assert_eq!(handshake(Some("13"), Some("dGhlIHNhbXBsZSBub25jZQ=="), false).await.0, StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(handshake(Some("8"), Some("dGhlIHNhbXBsZSBub25jZQ=="), true).await.0, StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(handshake(Some("13"), None, true).await.0, StatusCode::BAD_REQUEST);
+
+    // only the protocol switched to is named, not everything offered
+    let req = HyperRequest::get("/chat/lobby")
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "h2c, websocket")
+        .header(header::SEC_WEBSOCKET_VERSION, "13")
+        .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==");
+    let req = Request::from_internal(req.body(Body::empty()).unwrap(), None, data.clone());
+    let res = stack.invoke(req, response(data.clone())).await;
+    assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(res.headers()[header::UPGRADE], "websocket");
+}
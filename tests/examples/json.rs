@@ -67,7 +67,7 @@ mod outgoing {
         fn sets_content_type_header() {
             with_path("/Pea/Nut", |res| {
                 let content_type = res.headers.get::<header::ContentType>().unwrap();
-                let expected: mime::Mime = "application/json".parse().unwrap();
+                let expected: mime::Mime = "application/json; charset=utf-8".parse().unwrap();
                 assert_eq!(content_type, &header::ContentType(expected));
             })
         }